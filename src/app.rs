@@ -2,21 +2,154 @@ use eframe::egui;
 use eframe::egui::{FontDefinitions, FontFamily};
 use egui_commonmark::{CommonMarkCache, CommonMarkViewer};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender, channel};
 
-use crate::openai::Role;
+use crate::attachments::{self, Attachment, ImageAttachment};
+use crate::commands::{self, Command, ExportFormat, Setting};
+use crate::draft::{DraftEditor, DraftKey, DraftOutcome, Registers};
+use crate::editor::{self, EditorConfig};
+use crate::keybindings::{Action, Keybindings};
+use crate::mcp::ServerStatus;
+use crate::openai::{ChatReply, ChatRequest, Role, ToolCall, ToolResult, ToolRound};
+use crate::templates::{self, Template};
+use crate::tools::ToolRegistry;
+use crate::vim::{NormalCommand, NormalState};
 use crate::{default_models, fetch_history, get_completions_url, load_or_create_config, openai};
 
+const DEFAULT_SYSTEM_PROMPT: &str =
+    "You are a helpful assistant. You can use markdown formatting in your responses.";
+const DEFAULT_TEMPERATURE: f32 = 0.7;
+// Files listed in the `@` completion popup
+const MENTION_LIMIT: usize = 12;
+// Follow-up requests with tool results before giving up on an answer
+const MAX_TOOL_ROUNDS: usize = 10;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum EditMode {
     Normal,
     Insert,
+    Command,
+}
+
+// What NORMAL mode keys act on: scrolling the chat or editing the draft
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum NormalContext {
+    Chat,
+    Draft,
+}
+
+// Template picker window: the list of templates, then a form for the chosen one
+struct TemplatePicker {
+    templates: Vec<Template>,
+    filter: String,
+    selected: Option<usize>,
+    values: BTreeMap<String, String>,
+    error: Option<String>,
+    // Move the keyboard to the first field once the form is shown
+    focus_first_field: bool,
+}
+
+impl TemplatePicker {
+    fn select(&mut self, index: usize) {
+        if let Some(template) = self.templates.get(index) {
+            self.values = templates::variables(&template.body)
+                .into_iter()
+                .map(|name| {
+                    let value = template.defaults.get(&name).cloned().unwrap_or_default();
+                    (name, value)
+                })
+                .collect();
+            self.selected = Some(index);
+            self.error = None;
+            self.focus_first_field = true;
+        }
+    }
+}
+
+// Tool calls from the last reply, confirmed and run one at a time before the
+// request is sent again with their results
+struct PendingTools {
+    request: ChatRequest,
+    round: ToolRound,
+    // Output of the call being run
+    running: Option<Receiver<String>>,
+}
+
+impl PendingTools {
+    fn next_call(&self) -> Option<&ToolCall> {
+        self.round.calls.get(self.round.results.len())
+    }
+}
+
+fn input_id() -> egui::Id {
+    egui::Id::new("draft_input")
+}
+
+fn input_cursor(ctx: &egui::Context) -> Option<usize> {
+    egui::TextEdit::load_state(ctx, input_id())
+        .and_then(|state| state.cursor.char_range())
+        .map(|range| range.primary.index)
+}
+
+fn set_input_selection(ctx: &egui::Context, start: usize, end: usize) {
+    let mut state = egui::TextEdit::load_state(ctx, input_id()).unwrap_or_default();
+    let range = egui::text::CCursorRange::two(
+        egui::text::CCursor::new(start),
+        egui::text::CCursor::new(end),
+    );
+    state.cursor.set_char_range(Some(range));
+    state.store(ctx, input_id());
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ChatMessage {
     pub role: Role,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageAttachment>,
+    // Calls the assistant made, or for a tool message the call it answers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+// Arguments of a tool call, pretty-printed when they are valid JSON
+fn format_arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| arguments.to_string())
+}
+
+fn messages_to_markdown(messages: &[ChatMessage]) -> String {
+    let mut markdown = String::new();
+    for message in messages {
+        let heading = match message.role {
+            Role::System => "System",
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+            Role::Tool => "Tool",
+        };
+        markdown.push_str(&format!(
+            "## {}\n\n{}\n\n",
+            heading,
+            message.content.trim_end()
+        ));
+        for image in &message.images {
+            markdown.push_str(&format!("![{}]({})\n\n", image.name, image.url));
+        }
+        if !matches!(message.role, Role::Tool) {
+            for call in &message.tool_calls {
+                markdown.push_str(&format!(
+                    "`{}`\n```json\n{}\n```\n\n",
+                    call.function.name,
+                    format_arguments(&call.function.arguments)
+                ));
+            }
+        }
+    }
+    markdown
 }
 
 #[allow(dead_code)]
@@ -25,8 +158,8 @@ pub struct MyApp {
     pub messages: Vec<ChatMessage>,
     pub input: String,
     pub http_client: reqwest::Client,
-    pub response_rx: Receiver<Result<String, String>>,
-    pub request_tx: Sender<String>,
+    pub response_rx: Receiver<Result<ChatReply, String>>,
+    pub request_tx: Sender<ChatRequest>,
     pub is_processing: bool,
     pub markdown_cache: CommonMarkCache,
     pub selected_model: String,
@@ -39,6 +172,32 @@ pub struct MyApp {
     pub message_tops: Vec<f32>,
     pub copy_button_tops: Vec<f32>,
     pub last_scroll_area_height: f32,
+    pub system_prompt: String,
+    pub temperature: f32,
+    pub marks: HashMap<char, f32>,
+    pub command_line: String,
+    pub status_message: Option<String>,
+    normal_state: NormalState,
+    normal_context: NormalContext,
+    draft_editor: DraftEditor,
+    registers: Registers,
+    keybindings: Keybindings,
+    drop_focus: bool,
+    editor_config: EditorConfig,
+    // Result of composing in the external editor, and whether to send it after
+    editor_rx: Option<Receiver<Result<String, String>>>,
+    send_after_edit: bool,
+    template_picker: Option<TemplatePicker>,
+    // Files sent with the next message, and where relative paths and `@` mentions start from
+    attachments: Vec<Attachment>,
+    images: Vec<ImageAttachment>,
+    working_dir: PathBuf,
+    // `@` completions for the last query, so the tree is only walked when it changes
+    mention_matches: Option<(String, Vec<String>)>,
+    tools: ToolRegistry,
+    // The request being answered, kept in case the model calls tools
+    last_request: Option<ChatRequest>,
+    pending_tools: Option<PendingTools>,
 }
 
 #[allow(dead_code)]
@@ -46,28 +205,42 @@ impl MyApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // Load or create config
         let config = load_or_create_config();
+        // Keybinding problems are reported once at startup
+        let (keybindings, keybinding_warnings) =
+            Keybindings::from_config(config.keybindings.as_ref());
+        for warning in &keybinding_warnings {
+            eprintln!("config.toml: {}", warning);
+        }
+        let editor_config = config.editor.clone().unwrap_or_default();
+        let (tools, tool_warnings) =
+            ToolRegistry::from_config(config.tools.as_ref(),
+            config.mcp_servers.as_ref(),
+            config.shell.as_ref(),
+        );
+        for warning in &tool_warnings {
+            eprintln!("config.toml: {}", warning);
+        }
+        let startup_warnings: Vec<String> = keybinding_warnings
+            .into_iter()
+            .chain(tool_warnings)
+            .collect();
         // Initialize HTTP client
         let http_client = reqwest::Client::new();
 
         // Set up channels for async communication
-        let (request_tx, request_rx): (Sender<String>, Receiver<String>) = channel();
-        let (response_tx, response_rx): (
-            Sender<Result<String, String>>,
-            Receiver<Result<String, String>>,
-        ) = channel();
+        let (request_tx, request_rx) = channel::<ChatRequest>();
+        let (response_tx, response_rx) = channel();
 
         // Spawn background thread for handling API requests
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            while let Ok(message) = request_rx.recv() {
+            while let Ok(request) = request_rx.recv() {
                 let tx = response_tx.clone();
-                let (content, model) = message.split_once('\0').unwrap();
                 let api_key = config.openai_api_key.as_deref().unwrap_or("");
                 let api_url = get_completions_url(&config.api_url);
                 rt.block_on(async {
                     let result =
-                        openai::send_openai_request(content, model, api_key, api_url.as_str())
-                            .await;
+                        openai::send_openai_request(&request, api_key, api_url.as_str()).await;
                     tx.send(result).unwrap();
                 });
             }
@@ -78,17 +251,16 @@ impl MyApp {
         // Add initial system message
         messages.push(ChatMessage {
             role: Role::System,
-            content:
-                "You are a helpful assistant. You can use markdown formatting in your responses."
-                    .to_string(),
+            content: DEFAULT_SYSTEM_PROMPT.to_string(),
+            ..Default::default()
         });
         // Fetch history and append
         let mut pending_scroll = None;
-        if let Ok(rt) = tokio::runtime::Runtime::new() {
-            if let Ok(history) = rt.block_on(fetch_history()) {
-                messages.extend(history);
-                pending_scroll = Some(100_000.0);
-            }
+        if let Ok(rt) = tokio::runtime::Runtime::new()
+            && let Ok(history) = rt.block_on(fetch_history())
+        {
+            messages.extend(history);
+            pending_scroll = Some(100_000.0);
         }
         let models = config.models.clone().unwrap_or_else(default_models);
 
@@ -138,7 +310,7 @@ impl MyApp {
             request_tx,
             is_processing: false,
             markdown_cache: CommonMarkCache::default(),
-            selected_model: models.get(0).cloned().unwrap_or_default(),
+            selected_model: models.first().cloned().unwrap_or_default(),
             history_rx: None,
             models,
             edit_mode: EditMode::Insert,
@@ -148,6 +320,28 @@ impl MyApp {
             message_tops: Vec::new(),
             copy_button_tops: Vec::new(),
             last_scroll_area_height: 0.0,
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            temperature: DEFAULT_TEMPERATURE,
+            marks: HashMap::new(),
+            command_line: String::new(),
+            status_message: (!startup_warnings.is_empty()).then(|| startup_warnings.join("; ")),
+            normal_state: NormalState::default(),
+            normal_context: NormalContext::Chat,
+            draft_editor: DraftEditor::default(),
+            registers: Registers::default(),
+            keybindings,
+            drop_focus: false,
+            editor_config,
+            editor_rx: None,
+            send_after_edit: false,
+            template_picker: None,
+            attachments: Vec::new(),
+            images: Vec::new(),
+            working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            mention_matches: None,
+            tools,
+            last_request: None,
+            pending_tools: None,
         }
     }
 
@@ -156,21 +350,248 @@ impl MyApp {
     }
 
     fn send_message(&mut self) {
-        if self.input.trim().is_empty() || self.is_processing {
+        if self.input.trim().is_empty() && self.attachments.is_empty() && self.images.is_empty() {
+            return;
+        }
+        // A draft starting with a single slash is a local command
+        if let Some(line) = self.slash_command() {
+            let line = line.to_string();
+            self.input.clear();
+            self.run_command_line(&line);
+            return;
+        }
+        if self.is_processing {
             return;
         }
 
-        let message = ChatMessage {
+        // Files mentioned as `@path` go with the message even if they weren't picked from the popup
+        for path in attachments::mentioned_files(&self.input, &self.working_dir) {
+            if let Err(error) = self.attach_file(&path) {
+                self.status_message = Some(format!("E: {}", error));
+            }
+        }
+        let mut content = std::mem::take(&mut self.input);
+        // `//` sends a message that starts with a slash
+        if content.starts_with("//") {
+            content.remove(0);
+        }
+        let content = attachments::message_with_attachments(&content, &self.attachments);
+        self.attachments.clear();
+        let images = std::mem::take(&mut self.images);
+        let image_urls = images.iter().map(|image| image.url.clone()).collect();
+        self.messages.push(ChatMessage {
             role: Role::User,
-            content: self.input.clone(),
+            content: content.clone(),
+            images,
+            ..Default::default()
+        });
+        self.send_request(content, image_urls);
+    }
+
+    // The draft without its leading slash, if it is a slash command
+    fn slash_command(&self) -> Option<&str> {
+        self.input
+            .strip_prefix('/')
+            .filter(|line| !line.starts_with('/') && !line.contains('\n'))
+    }
+
+    fn send_request(&mut self, content: String, images: Vec<String>) {
+        self.scroll_to_bottom();
+        let request = ChatRequest {
+            content,
+            images,
+            model: self.selected_model.clone(),
+            system_prompt: self.system_prompt.clone(),
+            temperature: self.temperature,
+            tools: self.tools.definitions(),
+            tool_rounds: Vec::new(),
+        };
+        self.last_request = Some(request.clone());
+        self.request_tx.send(request).ok();
+        self.is_processing = true;
+    }
+
+    fn handle_reply(&mut self, reply: ChatReply) {
+        if reply.tool_calls.is_empty() {
+            self.messages.push(ChatMessage {
+                role: Role::Assistant,
+                content: reply.content,
+                ..Default::default()
+            });
+            self.is_processing = false;
+            return;
+        }
+        self.messages.push(ChatMessage {
+            role: Role::Assistant,
+            content: reply.content.clone(),
+            tool_calls: reply.tool_calls.clone(),
+            ..Default::default()
+        });
+        let Some(request) = self.last_request.take() else {
+            self.is_processing = false;
+            return;
+        };
+        if request.tool_rounds.len() >= MAX_TOOL_ROUNDS {
+            self.messages.push(ChatMessage {
+                role: Role::System,
+                content: format!("Stopped after {} rounds of tool calls", MAX_TOOL_ROUNDS),
+                ..Default::default()
+            });
+            self.is_processing = false;
+            return;
+        }
+        // Stays processing until the calls are answered and the follow-up comes back
+        self.pending_tools = Some(PendingTools {
+            request,
+            round: ToolRound {
+                content: reply.content,
+                calls: reply.tool_calls,
+                results: Vec::new(),
+            },
+            running: None,
+        });
+    }
+
+    // Record the result of the next pending call, and send the follow-up once all are answered
+    fn finish_tool_call(&mut self, content: String) {
+        let Some(pending) = &mut self.pending_tools else {
+            return;
+        };
+        let Some(call) = pending.next_call().cloned() else {
+            return;
         };
-        self.messages.push(message);
+        pending.running = None;
+        pending.round.results.push(ToolResult {
+            call_id: call.id.clone(),
+            content: content.clone(),
+        });
+        let answered = pending.next_call().is_none();
+        self.messages.push(ChatMessage {
+            role: Role::Tool,
+            content,
+            tool_calls: vec![call],
+            ..Default::default()
+        });
         self.scroll_to_bottom();
+        if answered
+            && let Some(PendingTools {
+                mut request, round, ..
+            }) = self.pending_tools.take()
+        {
+            request.tool_rounds.push(round);
+            self.last_request = Some(request.clone());
+            self.request_tx.send(request).ok();
+        }
+    }
 
-        // Send request
-        self.request_tx.send(self.input.clone()).ok();
-        self.input.clear();
-        self.is_processing = true;
+    fn run_tool_call(&mut self) {
+        let Some(pending) = &mut self.pending_tools else {
+            return;
+        };
+        let Some(call) = pending.next_call() else {
+            return;
+        };
+        let Some(tool) = self.tools.runner(&call.function.name) else {
+            let error = format!("Unknown tool: {}", call.function.name);
+            self.finish_tool_call(error);
+            return;
+        };
+        let arguments = call.function.arguments.clone();
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            tx.send(tool.run(&arguments)).ok();
+        });
+        pending.running = Some(rx);
+    }
+
+    // Ask before running each call the model makes
+    fn show_tool_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.pending_tools else {
+            return;
+        };
+        let Some(call) = pending.next_call() else {
+            return;
+        };
+        if pending.running.is_some() {
+            return;
+        }
+        let runner = self.tools.runner(&call.function.name);
+        let details = runner
+            .as_ref()
+            .and_then(|runner| runner.preview(&call.function.arguments))
+            .unwrap_or_else(|| format_arguments(&call.function.arguments));
+        let mut run = ctx.input(|i| i.key_pressed(egui::Key::Enter));
+        let mut deny = ctx.input(|i| i.key_pressed(egui::Key::Escape));
+        egui::Window::new("Run tool?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(
+                    egui::RichText::new(&call.function.name)
+                        .strong()
+                        .monospace(),
+                );
+                if runner.is_none() {
+                    ui.colored_label(ui.visuals().error_fg_color, "This tool is not configured");
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.label(egui::RichText::new(&details).monospace());
+                    });
+                ui.horizontal(|ui| {
+                    run |= ui.button("Run").on_hover_text("Enter").clicked();
+                    deny |= ui.button("Deny").on_hover_text("Escape").clicked();
+                });
+            });
+        if deny {
+            self.finish_tool_call("The user declined to run this tool.".to_string());
+        } else if run {
+            self.run_tool_call();
+        }
+    }
+
+    // A dot per MCP server, colored by whether it is running
+    fn show_mcp_status(&self, ui: &mut egui::Ui) {
+        for server in self.tools.servers() {
+            let (color, detail) = match server.status() {
+                ServerStatus::Starting => (egui::Color32::YELLOW, "Starting".to_string()),
+                ServerStatus::Ready => (
+                    egui::Color32::GREEN,
+                    format!("{} tools", server.tools().len()),
+                ),
+                ServerStatus::Failed(error) => (ui.visuals().error_fg_color, error),
+            };
+            ui.label(egui::RichText::new("●").color(color))
+                .on_hover_text(format!("MCP server {}: {}", server.name, detail));
+            ui.label(&server.name);
+        }
+    }
+
+    // Tool calls as collapsible blocks, or for a tool message the result
+    fn show_tool_calls(ui: &mut egui::Ui, message: &ChatMessage) {
+        for call in &message.tool_calls {
+            let (title, body) = match message.role {
+                Role::Tool => (
+                    format!("✅ {} result", call.function.name),
+                    message.content.as_str(),
+                ),
+                _ => (
+                    format!("🔧 {}", call.function.name),
+                    call.function.arguments.as_str(),
+                ),
+            };
+            egui::CollapsingHeader::new(title)
+                .id_salt((&call.id, matches!(message.role, Role::Tool)))
+                .show(ui, |ui| {
+                    let text = match message.role {
+                        Role::Tool => body.to_string(),
+                        _ => format_arguments(body),
+                    };
+                    ui.label(egui::RichText::new(text).monospace());
+                });
+        }
     }
 
     fn refresh_history(&mut self) {
@@ -187,8 +608,690 @@ impl MyApp {
         self.history_rx = Some(rx);
     }
 
+    // Open the draft in the external editor; the text comes back through `editor_rx`
+    fn compose_in_editor(&mut self, send: bool) {
+        if self.editor_rx.is_some() {
+            return;
+        }
+        let (tx, rx) = channel();
+        let text = self.input.clone();
+        let config = self.editor_config.clone();
+        std::thread::spawn(move || {
+            tx.send(editor::edit_in_editor(&text, &config)).ok();
+        });
+        self.editor_rx = Some(rx);
+        self.send_after_edit = send || self.editor_config.send_on_exit;
+        self.status_message = Some("Waiting for the editor to exit...".to_string());
+    }
+
+    fn attach_file(&mut self, path: &Path) -> Result<String, String> {
+        if attachments::is_image(path) {
+            let image = attachments::load_image(path, &self.working_dir)?;
+            return Ok(self.attach_image(image));
+        }
+        let attachment = attachments::load_attachment(path, &self.working_dir)?;
+        if self.attachments.iter().any(|a| a.path == attachment.path) {
+            return Ok(format!("{} is already attached", attachment.name));
+        }
+        attachments::check_total_size(&self.attachments, &attachment)?;
+        let message = format!("Attached {}", attachment.name);
+        self.attachments.push(attachment);
+        Ok(message)
+    }
+
+    fn attach_image(&mut self, image: ImageAttachment) -> String {
+        let message = format!("Attached {}", image.name);
+        self.images.push(image);
+        message
+    }
+
+    fn paste_image(&mut self) -> Result<String, String> {
+        let image = attachments::clipboard_image()?;
+        Ok(self.attach_image(image))
+    }
+
+    // Attach files from the dialog or dropped on the window, reporting each in the status line
+    fn attach_files(&mut self, paths: Vec<PathBuf>) {
+        let notes: Vec<String> = paths
+            .iter()
+            .map(|path| match self.attach_file(path) {
+                Ok(note) => note,
+                Err(error) => format!("E: {}", error),
+            })
+            .collect();
+        self.status_message = (!notes.is_empty()).then(|| notes.join("; "));
+    }
+
+    fn pick_attachments(&mut self) {
+        if let Some(paths) = rfd::FileDialog::new()
+            .set_directory(&self.working_dir)
+            .pick_files()
+        {
+            self.attach_files(paths);
+        }
+    }
+
+    // Attached files as removable chips above the input
+    fn show_attachment_chips(&mut self, ui: &mut egui::Ui) {
+        if self.attachments.is_empty() && self.images.is_empty() {
+            return;
+        }
+        let mut removed = None;
+        let mut removed_image = None;
+        let ctx = ui.ctx().clone();
+        ui.horizontal_wrapped(|ui| {
+            for (index, attachment) in self.attachments.iter().enumerate() {
+                egui::Frame::group(ui.style())
+                    .inner_margin(egui::Margin::symmetric(6, 2))
+                    .show(ui, |ui| {
+                        ui.label(format!("📄 {}", attachment.name))
+                            .on_hover_text(attachment.path.display().to_string());
+                        ui.label(egui::RichText::new(attachment.size_label()).weak());
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            removed = Some(index);
+                        }
+                    });
+            }
+            for (index, image) in self.images.iter_mut().enumerate() {
+                egui::Frame::group(ui.style())
+                    .inner_margin(egui::Margin::symmetric(6, 2))
+                    .show(ui, |ui| {
+                        if let Some(thumbnail) = image.thumbnail(&ctx) {
+                            ui.add(egui::Image::new(thumbnail).max_height(32.0))
+                                .on_hover_text(&image.name);
+                        } else {
+                            ui.label(format!("🖼 {}", image.name));
+                        }
+                        ui.label(egui::RichText::new(image.size_label()).weak());
+                        if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                            removed_image = Some(index);
+                        }
+                    });
+            }
+        });
+        if let Some(index) = removed {
+            self.attachments.remove(index);
+        }
+        if let Some(index) = removed_image {
+            self.images.remove(index);
+        }
+    }
+
+    // The `@` mention being typed in the draft, with the char index of its `@`
+    fn current_mention(&self, ctx: &egui::Context) -> Option<(usize, String)> {
+        let cursor = input_cursor(ctx)?;
+        attachments::mention_at(&self.input, cursor)
+    }
+
+    fn mention_matches(&mut self, query: &str) -> Vec<String> {
+        match &self.mention_matches {
+            Some((last_query, matches)) if last_query == query => matches.clone(),
+            _ => {
+                let matches = attachments::fuzzy_find(&self.working_dir, query, MENTION_LIMIT);
+                self.mention_matches = Some((query.to_string(), matches.clone()));
+                matches
+            }
+        }
+    }
+
+    // Replace the `@query` before the cursor with the chosen path and attach the file
+    fn pick_mention(&mut self, ctx: &egui::Context, start: usize, path: &str) {
+        let cursor = input_cursor(ctx).unwrap_or(start);
+        let byte_index = |index: usize| {
+            self.input
+                .char_indices()
+                .nth(index)
+                .map_or(self.input.len(), |(i, _)| i)
+        };
+        let range = byte_index(start)..byte_index(cursor);
+        let replacement = format!("@{} ", path);
+        self.input.replace_range(range, &replacement);
+        let end = start + replacement.chars().count();
+        set_input_selection(ctx, end, end);
+        self.mention_matches = None;
+        let path = self.working_dir.join(path);
+        self.status_message = Some(match self.attach_file(&path) {
+            Ok(note) => note,
+            Err(error) => format!("E: {}", error),
+        });
+    }
+
+    fn complete_mention(&mut self, ctx: &egui::Context) {
+        let Some((start, query)) = self.current_mention(ctx) else {
+            return;
+        };
+        if let Some(path) = self.mention_matches(&query).first() {
+            self.pick_mention(ctx, start, path);
+        }
+    }
+
+    // Files matching the `@` mention being typed
+    fn show_mention_popup(&mut self, ctx: &egui::Context, input_rect: egui::Rect) {
+        let Some((start, query)) = self.current_mention(ctx) else {
+            return;
+        };
+        let matches = self.mention_matches(&query);
+        if matches.is_empty() {
+            return;
+        }
+        let mut picked = None;
+        egui::Area::new(egui::Id::new("mention_popup"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(input_rect.left_top())
+            .pivot(egui::Align2::LEFT_BOTTOM)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for path in &matches {
+                        let label = egui::RichText::new(format!("@{}", path)).monospace();
+                        if ui.selectable_label(false, label).clicked() {
+                            picked = Some(path.clone());
+                        }
+                    }
+                });
+            });
+        if let Some(path) = picked {
+            self.pick_mention(ctx, start, &path);
+        }
+    }
+
+    // Scroll offset of the message `count` steps after (or before) the current position
+    fn message_offset(&self, count: usize, forward: bool) -> Option<f32> {
+        let current = self.current_scroll_offset;
+        let index = if forward {
+            let next = self
+                .message_tops
+                .iter()
+                .position(|&top| top > current + 1.0)?;
+            (next + count - 1).min(self.message_tops.len() - 1)
+        } else {
+            let prev = self
+                .message_tops
+                .iter()
+                .rposition(|&top| top < current - 1.0)?;
+            prev.saturating_sub(count - 1)
+        };
+        self.message_tops.get(index).copied()
+    }
+
+    fn run_normal_command(
+        &mut self,
+        count: Option<usize>,
+        command: NormalCommand,
+        ctx: &egui::Context,
+    ) {
+        let n = count.unwrap_or(1).max(1);
+        let scroll_amount = 60.0; // One message height
+        let window_height = self.last_scroll_area_height;
+        match command {
+            NormalCommand::Insert => self.enter_insert_mode(),
+            NormalCommand::ScrollDown => {
+                self.pending_scroll = Some(self.current_scroll_offset + scroll_amount * n as f32);
+            }
+            NormalCommand::ScrollUp => {
+                let new_offset = self.current_scroll_offset - scroll_amount * n as f32;
+                self.pending_scroll = Some(new_offset.max(0.0));
+            }
+            // Shift+J/K: scroll by one window height
+            NormalCommand::PageDown => {
+                self.pending_scroll = Some(self.current_scroll_offset + window_height * n as f32);
+            }
+            NormalCommand::PageUp => {
+                let new_offset = self.current_scroll_offset - window_height * n as f32;
+                self.pending_scroll = Some(new_offset.max(0.0));
+            }
+            // With a count, g/G jump to that message like a line number in vim
+            NormalCommand::Top | NormalCommand::Bottom if count.is_some() => {
+                let index = (n - 1).min(self.message_tops.len().saturating_sub(1));
+                if let Some(&top) = self.message_tops.get(index) {
+                    self.pending_scroll = Some(top);
+                }
+            }
+            NormalCommand::Top => self.pending_scroll = Some(0.0),
+            NormalCommand::Bottom => self.scroll_to_bottom(),
+            NormalCommand::NextMessage => {
+                if let Some(top) = self.message_offset(n, true) {
+                    self.pending_scroll = Some(top);
+                }
+            }
+            NormalCommand::PrevMessage => {
+                let top = self.message_offset(n, false).unwrap_or(0.0);
+                self.pending_scroll = Some(top);
+            }
+            NormalCommand::Refresh => self.refresh_history(),
+            NormalCommand::SetMark(mark) => {
+                self.marks.insert(mark, self.current_scroll_offset);
+            }
+            NormalCommand::JumpMark(mark) => match self.marks.get(&mark) {
+                Some(&offset) => self.pending_scroll = Some(offset),
+                None => self.status_message = Some(format!("Mark not set: {}", mark)),
+            },
+            NormalCommand::YankMessage(register) => {
+                // The message at the top of the view
+                let current = self.current_scroll_offset;
+                let index = self
+                    .message_tops
+                    .iter()
+                    .rposition(|&top| top <= current + 1.0)
+                    .unwrap_or(0);
+                if let Some(message) = self.messages.get(index) {
+                    self.registers.set(register, message.content.clone());
+                    if register == Some('+') {
+                        ctx.copy_text(message.content.clone());
+                    }
+                    self.status_message = Some(format!("Yanked message {}", index + 1));
+                }
+            }
+            NormalCommand::SwitchContext => self.toggle_normal_context(),
+            NormalCommand::Compose => self.compose_in_editor(false),
+            NormalCommand::Templates => self.open_template_picker(None),
+            NormalCommand::PasteImage => {
+                self.status_message = Some(match self.paste_image() {
+                    Ok(note) => note,
+                    Err(error) => format!("E: {}", error),
+                });
+            }
+            NormalCommand::CommandLine => {
+                self.command_line.clear();
+                self.status_message = None;
+                self.edit_mode = EditMode::Command;
+            }
+        }
+    }
+
+    fn complete_command_line(&mut self) {
+        let candidates = commands::complete_in_place(&mut self.command_line, &self.models);
+        if !candidates.is_empty() {
+            let options: Vec<&str> = candidates
+                .iter()
+                .map(|c| c.rsplit(' ').next().unwrap_or(c))
+                .collect();
+            self.status_message = Some(options.join("  "));
+        }
+    }
+
+    fn complete_slash_command(&mut self, ctx: &egui::Context) {
+        let Some(line) = self.slash_command() else {
+            return;
+        };
+        let mut line = line.to_string();
+        commands::complete_in_place(&mut line, &self.models);
+        self.input = format!("/{}", line);
+        let end = self.input.chars().count();
+        set_input_selection(ctx, end, end);
+    }
+
+    // Commands and arguments matching the slash command being typed
+    fn show_slash_popup(&mut self, ctx: &egui::Context, input_rect: egui::Rect) {
+        let Some(line) = self.slash_command() else {
+            return;
+        };
+        let candidates = commands::complete(line, &self.models);
+        if candidates.is_empty() || candidates.iter().any(|c| c == line.trim_end()) {
+            return;
+        }
+        let mut picked = None;
+        egui::Area::new(egui::Id::new("slash_popup"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(input_rect.left_top())
+            .pivot(egui::Align2::LEFT_BOTTOM)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for candidate in candidates.iter().take(12) {
+                        let text = match commands::command_info(candidate) {
+                            Some(info) => format!("/{:<24} {}", info.usage, info.description),
+                            None => format!("/{}", candidate),
+                        };
+                        let label = egui::RichText::new(text).monospace();
+                        if ui.selectable_label(false, label).clicked() {
+                            picked = Some(candidate.clone());
+                        }
+                    }
+                });
+            });
+        if let Some(candidate) = picked {
+            self.input = format!("/{}", candidate);
+            if !candidate.contains(' ') {
+                self.input.push(' ');
+            }
+            let end = self.input.chars().count();
+            set_input_selection(ctx, end, end);
+        }
+    }
+
+    fn execute_command_line(&mut self) {
+        let line = std::mem::take(&mut self.command_line);
+        self.edit_mode = EditMode::Normal;
+        self.run_command_line(&line);
+    }
+
+    fn run_command_line(&mut self, line: &str) {
+        match commands::parse_command(line).and_then(|command| self.execute_command(command)) {
+            Ok(Some(message)) => self.status_message = Some(message),
+            Ok(None) => self.status_message = None,
+            Err(error) => self.status_message = Some(format!("E: {}", error)),
+        }
+    }
+
+    fn execute_command(&mut self, command: Command) -> Result<Option<String>, String> {
+        match command {
+            Command::Model(model) => {
+                if !self.models.contains(&model) {
+                    return Err(format!("Unknown model: {}", model));
+                }
+                self.selected_model = model;
+                Ok(None)
+            }
+            Command::Export { format, path } => {
+                let contents = match format {
+                    ExportFormat::Markdown => messages_to_markdown(&self.messages),
+                    ExportFormat::Json => {
+                        serde_json::to_string_pretty(&self.messages).map_err(|e| e.to_string())?
+                    }
+                };
+                fs::write(&path, contents).map_err(|e| e.to_string())?;
+                Ok(Some(format!("Exported to {}", path.display())))
+            }
+            Command::New => {
+                self.system_prompt = DEFAULT_SYSTEM_PROMPT.to_string();
+                self.temperature = DEFAULT_TEMPERATURE;
+                self.messages = vec![ChatMessage {
+                    role: Role::System,
+                    content: self.system_prompt.clone(),
+                    ..Default::default()
+                }];
+                self.marks.clear();
+                self.pending_scroll = Some(0.0);
+                Ok(None)
+            }
+            Command::Clear => {
+                // Keep the system message, drop the rest
+                self.messages.truncate(1);
+                self.marks.clear();
+                self.pending_scroll = Some(0.0);
+                Ok(None)
+            }
+            Command::Retry => {
+                if self.is_processing {
+                    return Err("A request is already running".to_string());
+                }
+                let index = self
+                    .messages
+                    .iter()
+                    .rposition(|m| matches!(m.role, Role::User))
+                    .ok_or_else(|| "No message to retry".to_string())?;
+                let message = &self.messages[index];
+                let content = message.content.clone();
+                let images = message
+                    .images
+                    .iter()
+                    .map(|image| image.url.clone())
+                    .collect();
+                // Drop the answer (or error) being retried
+                self.messages.truncate(index + 1);
+                self.send_request(content, images);
+                Ok(None)
+            }
+            Command::Template(name) => {
+                self.open_template_picker(name.as_deref());
+                Ok(None)
+            }
+            Command::File(path) => {
+                let attachment = attachments::load_attachment(&path, &self.working_dir)?;
+                if !self.input.trim().is_empty() {
+                    self.input.push_str("\n\n");
+                }
+                self.input.push_str(&attachment.to_markdown());
+                Ok(Some(format!("Added {}", attachment.name)))
+            }
+            Command::Attach(Some(path)) => self.attach_file(&path).map(Some),
+            Command::PasteImage => self.paste_image().map(Some),
+            Command::Attach(None) => {
+                self.pick_attachments();
+                Ok(self.status_message.clone())
+            }
+            Command::System(prompt) => {
+                if prompt.is_empty() {
+                    return Ok(Some(self.system_prompt.clone()));
+                }
+                if let Some(first) = self.messages.first_mut() {
+                    first.content = prompt.clone();
+                }
+                self.system_prompt = prompt;
+                Ok(None)
+            }
+            Command::Edit { send } => {
+                self.compose_in_editor(send);
+                Ok(self.status_message.clone())
+            }
+            Command::Set(Setting::Temperature(temperature)) => {
+                self.temperature = temperature;
+                Ok(Some(format!("temperature={}", temperature)))
+            }
+        }
+    }
+
+    fn enter_insert_mode(&mut self) {
+        self.draft_editor.begin_insert(&self.input);
+        self.edit_mode = EditMode::Insert;
+    }
+
+    fn toggle_normal_context(&mut self) {
+        self.normal_state.reset();
+        self.draft_editor.visual_anchor = None;
+        self.normal_context = match self.normal_context {
+            NormalContext::Chat => {
+                self.draft_editor.clamp_cursor(&self.input);
+                NormalContext::Draft
+            }
+            NormalContext::Draft => NormalContext::Chat,
+        };
+    }
+
+    // Feed NORMAL mode keys to the draft editor instead of the chat view
+    fn handle_draft_keys(&mut self, events: &[egui::Event], ctx: &egui::Context) {
+        for event in events {
+            if self.edit_mode != EditMode::Normal || self.normal_context != NormalContext::Draft {
+                break;
+            }
+            if self.keybindings.normal_action(event) == Some(Action::SwitchContext) {
+                self.toggle_normal_context();
+                continue;
+            }
+            let keys: Vec<DraftKey> = match event {
+                egui::Event::Text(text) => text.chars().map(DraftKey::Char).collect(),
+                egui::Event::Key {
+                    key: egui::Key::Escape,
+                    pressed: true,
+                    ..
+                } => vec![DraftKey::Escape],
+                egui::Event::Key {
+                    key: egui::Key::R,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } if modifiers.ctrl => vec![DraftKey::Redo],
+                _ => continue,
+            };
+            for key in keys {
+                if self.edit_mode != EditMode::Normal {
+                    break;
+                }
+                let outcome = self
+                    .draft_editor
+                    .feed(key, &mut self.input, &mut self.registers);
+                match outcome {
+                    Some(DraftOutcome::Insert) => {
+                        let cursor = self.draft_editor.cursor;
+                        set_input_selection(ctx, cursor, cursor);
+                        self.edit_mode = EditMode::Insert;
+                    }
+                    // The + register is the system clipboard
+                    Some(DraftOutcome::Yanked(Some('+'))) => {
+                        if let Some(text) = self.registers.get(Some('+')) {
+                            ctx.copy_text(text.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // The keys were commands, so keep the TextEdit from typing them
+        ctx.input_mut(|i| {
+            i.events.retain(|event| {
+                !matches!(
+                    event,
+                    egui::Event::Text(_)
+                        | egui::Event::Key { .. }
+                        | egui::Event::Paste(_)
+                        | egui::Event::Cut
+                        | egui::Event::Copy
+                )
+            })
+        });
+    }
+
+    // Windows that take the keyboard away from the chat and the draft
+    fn dialog_open(&self) -> bool {
+        self.template_picker.is_some() || self.pending_tools.is_some()
+    }
+
+    fn open_template_picker(&mut self, name: Option<&str>) {
+        let (templates, errors) = templates::load_templates();
+        if templates.is_empty() && errors.is_empty() {
+            let dir = templates::templates_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default();
+            self.status_message = Some(format!("No templates in {}", dir));
+            return;
+        }
+        let mut picker = TemplatePicker {
+            templates,
+            filter: String::new(),
+            selected: None,
+            values: BTreeMap::new(),
+            error: (!errors.is_empty()).then(|| errors.join("\n")),
+            focus_first_field: false,
+        };
+        if let Some(name) = name {
+            match picker
+                .templates
+                .iter()
+                .position(|t| t.name.eq_ignore_ascii_case(name))
+            {
+                Some(index) => picker.select(index),
+                None => picker.filter = name.to_string(),
+            }
+        }
+        self.template_picker = Some(picker);
+    }
+
+    fn show_template_picker(&mut self, ctx: &egui::Context) {
+        let Some(picker) = &mut self.template_picker else {
+            return;
+        };
+        let mut open = !ctx.input(|i| i.key_pressed(egui::Key::Escape));
+        let mut rendered = None;
+        egui::Window::new("Templates")
+            .open(&mut open)
+            .collapsible(false)
+            .default_width(420.0)
+            .show(ctx, |ui| {
+                if let Some(error) = &picker.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                let Some(index) = picker.selected else {
+                    let filter = ui.add(
+                        egui::TextEdit::singleline(&mut picker.filter)
+                            .hint_text("Filter")
+                            .desired_width(f32::INFINITY),
+                    );
+                    filter.request_focus();
+                    let filter_text = picker.filter.to_lowercase();
+                    let matching: Vec<usize> = (0..picker.templates.len())
+                        .filter(|&i| {
+                            picker.templates[i]
+                                .name
+                                .to_lowercase()
+                                .contains(&filter_text)
+                        })
+                        .collect();
+                    let enter = ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let mut chosen = enter.then(|| matching.first().copied()).flatten();
+                    egui::ScrollArea::vertical()
+                        .max_height(300.0)
+                        .show(ui, |ui| {
+                            for &i in &matching {
+                                let template = &picker.templates[i];
+                                let response = ui.selectable_label(false, &template.name);
+                                let response = if template.description.is_empty() {
+                                    response
+                                } else {
+                                    response.on_hover_text(&template.description)
+                                };
+                                if response.clicked() {
+                                    chosen = Some(i);
+                                }
+                            }
+                        });
+                    if let Some(i) = chosen {
+                        picker.select(i);
+                    }
+                    return;
+                };
+
+                let template = &picker.templates[index];
+                ui.heading(&template.name);
+                if !template.description.is_empty() {
+                    ui.label(&template.description);
+                }
+                egui::Grid::new("template_variables")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        for (position, (name, value)) in picker.values.iter_mut().enumerate() {
+                            ui.label(name.as_str());
+                            let field = ui.add(
+                                egui::TextEdit::multiline(value)
+                                    .desired_rows(1)
+                                    .desired_width(300.0),
+                            );
+                            if position == 0 && picker.focus_first_field {
+                                field.request_focus();
+                            }
+                            ui.end_row();
+                        }
+                    });
+                picker.focus_first_field = false;
+                ui.horizontal(|ui| {
+                    if ui.button("Insert").clicked() {
+                        match templates::render(&template.body, &picker.values) {
+                            Ok(text) => rendered = Some(text),
+                            Err(error) => picker.error = Some(error),
+                        }
+                    }
+                    if ui.button("Back").clicked() {
+                        picker.selected = None;
+                        picker.error = None;
+                    }
+                });
+            });
+        if let Some(text) = rendered {
+            if !self.input.trim().is_empty() {
+                self.input.push_str("\n\n");
+            }
+            self.input.push_str(&text);
+            self.template_picker = None;
+            self.enter_insert_mode();
+            let end = self.input.chars().count();
+            set_input_selection(ctx, end, end);
+        } else if !open {
+            self.template_picker = None;
+        }
+    }
+
     fn handle_insert_mode(&mut self, text_edit: &egui::Response) {
-        text_edit.request_focus();
+        if !self.dialog_open() {
+            text_edit.request_focus();
+        }
     }
 
     fn handle_normal_mode(&mut self, text_edit: &egui::Response, ctx: &egui::Context) {
@@ -196,8 +1299,39 @@ impl MyApp {
             // Remove focus from the input box
             ctx.memory_mut(|mem| mem.surrender_focus(text_edit.id));
             // If the user just clicked, switch to Insert mode
-            self.edit_mode = EditMode::Insert;
+            self.enter_insert_mode();
+        }
+    }
+
+    fn handle_draft_mode(&mut self, text_edit: &egui::Response, ctx: &egui::Context) {
+        if self.dialog_open() {
+            return;
+        }
+        text_edit.request_focus();
+        // Tab, arrows and Escape are draft commands rather than focus changes
+        ctx.memory_mut(|m| {
+            m.set_focus_lock_filter(
+                text_edit.id,
+                egui::EventFilter {
+                    tab: true,
+                    horizontal_arrows: true,
+                    vertical_arrows: true,
+                    escape: true,
+                },
+            )
+        });
+        if text_edit.clicked() || text_edit.dragged() {
+            // Clicking moves the draft cursor
+            if let Some(cursor) = input_cursor(ctx) {
+                self.draft_editor.cursor = cursor;
+                self.draft_editor.clamp_cursor(&self.input);
+            }
         }
+        let (start, end) = self
+            .draft_editor
+            .selection()
+            .unwrap_or((self.draft_editor.cursor, self.draft_editor.cursor));
+        set_input_selection(ctx, start, end);
     }
 }
 
@@ -232,11 +1366,22 @@ impl eframe::App for MyApp {
                             ui.selectable_value(&mut self.selected_model, model.clone(), model);
                         }
                     });
+                self.show_mcp_status(ui);
                 // Add a spacer to push the mode indicator to the right
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     let (mode_text, bg_color, fg_color) = match self.edit_mode {
+                        EditMode::Normal if self.draft_editor.is_visual() => {
+                            ("VISUAL", egui::Color32::BLACK, egui::Color32::WHITE)
+                        }
                         EditMode::Normal => ("NORMAL", egui::Color32::BLACK, egui::Color32::WHITE),
                         EditMode::Insert => ("INSERT", egui::Color32::WHITE, egui::Color32::BLACK),
+                        EditMode::Command => {
+                            ("COMMAND", egui::Color32::BLACK, egui::Color32::WHITE)
+                        }
+                    };
+                    let pending_keys = match self.normal_context {
+                        NormalContext::Chat => self.normal_state.pending_keys(&self.keybindings),
+                        NormalContext::Draft => self.draft_editor.pending_keys(),
                     };
                     ui.label(
                         egui::RichText::new(mode_text)
@@ -246,64 +1391,170 @@ impl eframe::App for MyApp {
                             .monospace()
                             .size(16.0),
                     );
+                    if self.edit_mode == EditMode::Normal {
+                        let context = match self.normal_context {
+                            NormalContext::Chat => "CHAT",
+                            NormalContext::Draft => "DRAFT",
+                        };
+                        ui.label(egui::RichText::new(context).monospace().size(16.0))
+                            .on_hover_text(format!(
+                                "{} switches between the chat and the draft",
+                                self.keybindings.describe(Action::SwitchContext)
+                            ));
+                    }
+                    if !pending_keys.is_empty() {
+                        ui.label(egui::RichText::new(pending_keys).monospace().size(16.0));
+                    }
                 });
             });
         });
 
         // Handle modal editing key events
-        let input = ctx.input(|i| i.clone());
+        if std::mem::take(&mut self.drop_focus) && self.edit_mode == EditMode::Normal {
+            ctx.memory_mut(|m| {
+                if let Some(id) = m.focused() {
+                    m.surrender_focus(id);
+                }
+            });
+        }
+        self.show_template_picker(ctx);
+        self.show_tool_confirmation(ctx);
+        let mut input = ctx.input(|i| i.clone());
+        // Keys typed into a dialog are not commands
+        let dialog_open = self.dialog_open();
+        if dialog_open {
+            input.events.clear();
+        }
+        // The send chord is taken out before the TextEdit can turn Enter into a newline
+        let send_pressed = self.edit_mode == EditMode::Insert
+            && !dialog_open
+            && ctx.input_mut(|i| self.keybindings.consume_insert(i, Action::Send));
+        // Tab completes slash commands
+        if self.edit_mode == EditMode::Insert
+            && !dialog_open
+            && self.slash_command().is_some()
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab))
+        {
+            self.complete_slash_command(ctx);
+        }
+        // and `@` mentions
+        if self.edit_mode == EditMode::Insert
+            && !dialog_open
+            && self.current_mention(ctx).is_some()
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab))
+        {
+            self.complete_mention(ctx);
+        }
+        // Files dropped onto the window are attached
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        if !dropped.is_empty() {
+            self.attach_files(dropped);
+        }
         match self.edit_mode {
             EditMode::Insert => {
-                if input.key_pressed(egui::Key::Escape) {
+                if self.keybindings.insert_pressed(&input, Action::NormalMode) {
                     self.edit_mode = EditMode::Normal;
+                    let cursor = input_cursor(ctx).unwrap_or(self.input.chars().count());
+                    self.draft_editor.end_insert(&self.input, cursor);
                 }
             }
+            EditMode::Normal if self.normal_context == NormalContext::Draft => {
+                self.handle_draft_keys(&input.events, ctx);
+            }
             EditMode::Normal => {
-                if input.key_pressed(egui::Key::I)
-                    && !input.modifiers.shift
-                    && !input.modifiers.ctrl
-                    && !input.modifiers.alt
-                    && !input.modifiers.mac_cmd
-                    && !input.modifiers.command
-                {
-                    self.edit_mode = EditMode::Insert;
-                }
-                // j/k scrolling
-                let scroll_amount = 60.0; // One message height
-                if input.key_pressed(egui::Key::R) && !input.modifiers.any() {
-                    self.refresh_history();
-                }
-                if input.key_pressed(egui::Key::J) && !input.modifiers.shift {
-                    let new_offset = self.current_scroll_offset + scroll_amount;
-                    self.pending_scroll = Some(new_offset);
-                }
-                if input.key_pressed(egui::Key::K) && !input.modifiers.shift {
-                    let new_offset = (self.current_scroll_offset - scroll_amount).max(0.0);
-                    self.pending_scroll = Some(new_offset);
-                }
-                // Shift+J/K: scroll by one window height
-                let window_height = self.last_scroll_area_height;
-                if input.key_pressed(egui::Key::J) && input.modifiers.shift {
-                    let new_offset = self.current_scroll_offset + window_height;
-                    self.pending_scroll = Some(new_offset);
-                }
-                if input.key_pressed(egui::Key::K) && input.modifiers.shift {
-                    let new_offset = (self.current_scroll_offset - window_height).max(0.0);
-                    self.pending_scroll = Some(new_offset);
+                if input.key_pressed(egui::Key::Escape) {
+                    self.normal_state.reset();
+                    self.status_message = None;
                 }
-                // G (Shift+g) to scroll to bottom, g to scroll to top
-                if input.key_pressed(egui::Key::G) && input.modifiers.shift {
-                    self.scroll_to_bottom();
+                for event in &input.events {
+                    // Keys typed after leaving NORMAL mode belong to the new mode
+                    if self.edit_mode != EditMode::Normal {
+                        break;
+                    }
+                    let commands = match event {
+                        egui::Event::Text(text) => text
+                            .chars()
+                            .filter_map(|ch| self.normal_state.feed_char(ch, &self.keybindings))
+                            .collect(),
+                        egui::Event::Key {
+                            key,
+                            pressed: true,
+                            modifiers,
+                            ..
+                        } => {
+                            let command =
+                                self.normal_state
+                                    .feed_key(*key, *modifiers, &self.keybindings);
+                            // egui also moves focus on keys like Tab, undo that next frame
+                            self.drop_focus |= command.is_some();
+                            command.into_iter().collect()
+                        }
+                        _ => Vec::new(),
+                    };
+                    for (count, command) in commands {
+                        self.run_normal_command(count, command, ctx);
+                    }
                 }
-                if input.key_pressed(egui::Key::G) && !input.modifiers.shift {
-                    self.pending_scroll = Some(0.0);
+            }
+            EditMode::Command => {
+                if input.key_pressed(egui::Key::Escape) {
+                    self.command_line.clear();
+                    self.edit_mode = EditMode::Normal;
                 }
             }
         }
 
         // Bottom panel for input
         egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
+            if self.edit_mode == EditMode::Command {
+                // Tab completes instead of moving focus
+                let tab = ui.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab));
+                if tab {
+                    self.complete_command_line();
+                }
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new(":").monospace());
+                    let command_edit = ui.add(
+                        egui::TextEdit::singleline(&mut self.command_line)
+                            .desired_width(f32::INFINITY)
+                            .font(egui::TextStyle::Monospace)
+                            .lock_focus(true),
+                    );
+                    command_edit.request_focus();
+                    if tab {
+                        // Keep the cursor at the end of the completed text
+                        if let Some(mut state) = egui::TextEdit::load_state(ctx, command_edit.id) {
+                            let end = egui::text::CCursor::new(self.command_line.chars().count());
+                            state
+                                .cursor
+                                .set_char_range(Some(egui::text::CCursorRange::one(end)));
+                            state.store(ctx, command_edit.id);
+                        }
+                    }
+                    if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        self.execute_command_line();
+                    }
+                });
+            } else if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+                ui.label(egui::RichText::new("Drop files to attach them").monospace());
+            } else if let Some(status) = &self.status_message {
+                ui.label(egui::RichText::new(status).monospace());
+            }
+            self.show_attachment_chips(ui);
             ui.horizontal(|ui| {
+                if ui
+                    .button("📎")
+                    .on_hover_text("Attach files (or drop them on the window)")
+                    .clicked()
+                {
+                    self.pick_attachments();
+                }
                 // Make the text input take up as much width as possible
                 let available_width = ui.available_width();
                 let button_width = 80.0; // Reserve space for the button
@@ -312,13 +1563,18 @@ impl eframe::App for MyApp {
                         available_width - button_width,
                         60.0, // or your preferred height
                     ],
-                    egui::TextEdit::multiline(&mut self.input),
+                    egui::TextEdit::multiline(&mut self.input).id(input_id()),
                 );
-                match self.edit_mode {
-                    EditMode::Insert => {
+                match (self.edit_mode, self.normal_context) {
+                    (EditMode::Insert, _) => {
                         self.handle_insert_mode(&text_edit);
+                        self.show_slash_popup(ctx, text_edit.rect);
+                        self.show_mention_popup(ctx, text_edit.rect);
+                    }
+                    (EditMode::Normal, NormalContext::Draft) => {
+                        self.handle_draft_mode(&text_edit, ctx);
                     }
-                    EditMode::Normal => {
+                    (EditMode::Normal | EditMode::Command, _) => {
                         self.handle_normal_mode(&text_edit, ctx);
                     }
                 }
@@ -334,20 +1590,14 @@ impl eframe::App for MyApp {
                             egui::Button::new(if self.is_processing { "..." } else { "Send" })
                                 .min_size(egui::vec2(button_width, text_edit_height)),
                         )
+                        .on_hover_text(format!(
+                            "Send ({})",
+                            self.keybindings.describe(Action::Send)
+                        ))
                         .clicked()
-                        || (ui.input(|i| i.key_pressed(egui::Key::Enter) && !i.modifiers.shift)))
+                        || send_pressed)
                 {
-                    if !self.input.trim().is_empty() && !self.is_processing {
-                        let message = std::mem::take(&mut self.input);
-                        self.messages.push(ChatMessage {
-                            role: Role::User,
-                            content: message.clone(),
-                        });
-                        // Combine message and model with a null byte separator
-                        let request = format!("{}\0{}", message, self.selected_model);
-                        self.request_tx.send(request).unwrap();
-                        self.is_processing = true;
-                    }
+                    self.send_message();
                 } else {
                     ui.add_enabled(
                         false,
@@ -367,12 +1617,13 @@ impl eframe::App for MyApp {
             // Track Copy button top positions
             self.copy_button_tops.clear();
             self.message_tops.clear();
-            let mut y = 0.0;
             let output = scroll_area.show(ui, |ui| {
                 // Store the visible height of the scroll area for window jumps
                 self.last_scroll_area_height = ui.available_height();
-                for message in &self.messages {
-                    let before = ui.cursor().top();
+                // Message tops are stored relative to the content so they match scroll offsets
+                let content_top = ui.min_rect().top();
+                for message in &mut self.messages {
+                    let before = ui.cursor().top() - content_top;
                     ui.group(|ui| {
                         ui.horizontal(|ui| {
                             // Track Copy button position
@@ -382,18 +1633,39 @@ impl eframe::App for MyApp {
                                 .on_hover_text("Copy entire message markdown")
                                 .clicked()
                             {
-                                ui.output_mut(|o| o.copied_text = message.content.clone());
+                                ui.ctx().copy_text(message.content.clone());
                             }
                             self.copy_button_tops.push(copy_button_top);
                             // Add some spacing between the button and the text
                             ui.add_space(4.0);
-                            let viewer = CommonMarkViewer::new();
-                            viewer.show(ui, &mut self.markdown_cache, &message.content);
+                            ui.vertical(|ui| {
+                                // A tool result is shown in its collapsible block
+                                if !message.content.is_empty()
+                                    && !matches!(message.role, Role::Tool)
+                                {
+                                    let viewer = CommonMarkViewer::new();
+                                    viewer.show(ui, &mut self.markdown_cache, &message.content);
+                                }
+                                Self::show_tool_calls(ui, message);
+                            });
                         });
+                        if !message.images.is_empty() {
+                            ui.horizontal_wrapped(|ui| {
+                                let ctx = ui.ctx().clone();
+                                for image in &mut message.images {
+                                    match image.thumbnail(&ctx) {
+                                        Some(thumbnail) => {
+                                            ui.image(thumbnail).on_hover_text(&image.name);
+                                        }
+                                        None => {
+                                            ui.label(format!("🖼 {}", image.name));
+                                        }
+                                    }
+                                }
+                            });
+                        }
                     });
-                    let after = ui.cursor().top();
                     self.message_tops.push(before);
-                    y = after;
                     ui.add_space(8.0);
                 }
             });
@@ -403,46 +1675,72 @@ impl eframe::App for MyApp {
         });
 
         // Check for history refresh result
-        if let Some(rx) = &self.history_rx {
-            if let Ok(result) = rx.try_recv() {
-                match result {
-                    Ok(history) => {
-                        // Keep the system message, replace the rest
-                        if !self.messages.is_empty() {
-                            self.messages.truncate(1);
-                        }
-                        self.messages.extend(history);
+        if let Some(rx) = &self.history_rx
+            && let Ok(result) = rx.try_recv()
+        {
+            match result {
+                Ok(history) => {
+                    // Keep the system message, replace the rest
+                    if !self.messages.is_empty() {
+                        self.messages.truncate(1);
                     }
-                    Err(error) => {
-                        self.messages.push(ChatMessage {
-                            role: Role::System,
-                            content: format!("Error fetching history: {}", error),
-                        });
+                    self.messages.extend(history);
+                }
+                Err(error) => {
+                    self.messages.push(ChatMessage {
+                        role: Role::System,
+                        content: format!("Error fetching history: {}", error),
+                        ..Default::default()
+                    });
+                }
+            }
+            self.is_processing = false;
+            self.history_rx = None;
+        }
+
+        // Check for the draft coming back from the external editor
+        if let Some(rx) = &self.editor_rx
+            && let Ok(result) = rx.try_recv()
+        {
+            match result {
+                Ok(text) => {
+                    self.draft_editor.save_undo(&self.input);
+                    self.input = text.trim_end_matches('\n').to_string();
+                    self.draft_editor.clamp_cursor(&self.input);
+                    self.status_message = None;
+                    if self.send_after_edit {
+                        self.send_message();
                     }
                 }
-                self.is_processing = false;
-                self.history_rx = None;
+                Err(error) => self.status_message = Some(format!("E: {}", error)),
             }
+            self.editor_rx = None;
+        }
+
+        // Check for the output of a tool call
+        if let Some(pending) = &self.pending_tools
+            && let Some(rx) = &pending.running
+            && let Ok(output) = rx.try_recv()
+        {
+            self.finish_tool_call(output);
         }
 
         // Check for responses
         if let Ok(response) = self.response_rx.try_recv() {
             match response {
-                Ok(content) => {
-                    self.messages.push(ChatMessage {
-                        role: Role::Assistant,
-                        content,
-                    });
+                Ok(reply) => {
+                    self.handle_reply(reply);
                     self.scroll_to_bottom();
                 }
                 Err(error) => {
                     self.messages.push(ChatMessage {
                         role: Role::System,
                         content: format!("Error: {}", error),
+                        ..Default::default()
                     });
+                    self.is_processing = false;
                 }
             }
-            self.is_processing = false;
         }
     }
 
@@ -0,0 +1,328 @@
+// Local files attached to the next message. They are shown as chips above
+// the input; text files are inlined into the message as fenced code blocks
+// and images are sent as content parts for vision models.
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+const MAX_FILE_SIZE: u64 = 256 * 1024;
+const MAX_TOTAL_SIZE: usize = 1024 * 1024;
+// Enough to look for NUL bytes, which text files don't have
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+// Stop walking large trees when looking for `@` completions
+const MAX_WALKED_FILES: usize = 20_000;
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target", "dist", "build", "__pycache__"];
+const MAX_IMAGE_SIZE: u64 = 20 * 1024 * 1024;
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+// Longest side of the thumbnails shown in the chat and on the chips
+const THUMBNAIL_SIZE: u32 = 160;
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub path: PathBuf,
+    // Path as shown on the chip and in the message, relative to the working directory when possible
+    pub name: String,
+    pub contents: String,
+}
+
+impl Attachment {
+    pub fn size_label(&self) -> String {
+        let bytes = self.contents.len();
+        if bytes < 1024 {
+            format!("{} B", bytes)
+        } else {
+            format!("{:.1} KB", bytes as f64 / 1024.0)
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let language = self.path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        // Use a longer fence if the file itself contains one
+        let mut fence = "```".to_string();
+        while self.contents.contains(&fence) {
+            fence.push('`');
+        }
+        format!(
+            "`{}`\n{}{}\n{}\n{}",
+            self.name,
+            fence,
+            language,
+            self.contents.trim_end(),
+            fence
+        )
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImageAttachment {
+    pub name: String,
+    // The image as a base64 `data:` URI, which is how the API takes it
+    pub url: String,
+    #[serde(skip)]
+    thumbnail: Option<egui::TextureHandle>,
+}
+
+impl fmt::Debug for ImageAttachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImageAttachment")
+            .field("name", &self.name)
+            .field("bytes", &self.url.len())
+            .finish()
+    }
+}
+
+impl ImageAttachment {
+    // Images the API doesn't take everywhere are converted to PNG
+    fn from_bytes(name: String, bytes: Vec<u8>) -> Result<Self, String> {
+        let format = image::guess_format(&bytes)
+            .map_err(|_| format!("{} is not a supported image", name))?;
+        let (mime, bytes) = match format {
+            image::ImageFormat::Png => ("image/png", bytes),
+            image::ImageFormat::Jpeg => ("image/jpeg", bytes),
+            image::ImageFormat::Gif => ("image/gif", bytes),
+            image::ImageFormat::WebP => ("image/webp", bytes),
+            _ => {
+                let image =
+                    image::load_from_memory(&bytes).map_err(|e| format!("{}: {}", name, e))?;
+                ("image/png", encode_png(&image)?)
+            }
+        };
+        Ok(Self {
+            name,
+            url: format!("data:{};base64,{}", mime, BASE64.encode(bytes)),
+            thumbnail: None,
+        })
+    }
+
+    pub fn size_label(&self) -> String {
+        // Four base64 characters per three bytes
+        let bytes = self.url.len() / 4 * 3;
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+
+    // Decoded the first time the image is shown
+    pub fn thumbnail(&mut self, ctx: &egui::Context) -> Option<&egui::TextureHandle> {
+        if self.thumbnail.is_none() {
+            let (_, data) = self.url.split_once(";base64,")?;
+            let bytes = BASE64.decode(data).ok()?;
+            let image = image::load_from_memory(&bytes)
+                .ok()?
+                .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+                .into_rgba8();
+            let size = [image.width() as usize, image.height() as usize];
+            let pixels = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+            self.thumbnail = Some(ctx.load_texture(&self.name, pixels, Default::default()));
+        }
+        self.thumbnail.as_ref()
+    }
+}
+
+fn encode_png(image: &image::DynamicImage) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+pub fn load_image(path: &Path, working_dir: &Path) -> Result<ImageAttachment, String> {
+    let path = working_dir.join(path);
+    let metadata = fs::metadata(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if metadata.len() > MAX_IMAGE_SIZE {
+        return Err(format!(
+            "{} is larger than {} MB",
+            path.display(),
+            MAX_IMAGE_SIZE / 1024 / 1024
+        ));
+    }
+    let bytes = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let name = path
+        .strip_prefix(working_dir)
+        .unwrap_or(&path)
+        .display()
+        .to_string();
+    ImageAttachment::from_bytes(name, bytes)
+}
+
+// The image on the system clipboard, as a PNG
+pub fn clipboard_image() -> Result<ImageAttachment, String> {
+    let data = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .map_err(|e| format!("No image on the clipboard: {}", e))?;
+    let image = image::RgbaImage::from_raw(
+        data.width as u32,
+        data.height as u32,
+        data.bytes.into_owned(),
+    )
+    .ok_or_else(|| "The clipboard image could not be read".to_string())?;
+    let png = encode_png(&image::DynamicImage::ImageRgba8(image))?;
+    ImageAttachment::from_bytes("clipboard.png".to_string(), png)
+}
+
+pub fn load_attachment(path: &Path, working_dir: &Path) -> Result<Attachment, String> {
+    let path = if path.is_relative() {
+        working_dir.join(path)
+    } else {
+        path.to_path_buf()
+    };
+    let metadata = fs::metadata(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if metadata.is_dir() {
+        return Err(format!("{} is a directory", path.display()));
+    }
+    if metadata.len() > MAX_FILE_SIZE {
+        return Err(format!(
+            "{} is larger than {} KB",
+            path.display(),
+            MAX_FILE_SIZE / 1024
+        ));
+    }
+    let bytes = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let sniff = &bytes[..bytes.len().min(BINARY_SNIFF_LEN)];
+    if sniff.contains(&0) {
+        return Err(format!(
+            "{} looks like a binary file and was not attached",
+            path.display()
+        ));
+    }
+    let contents = String::from_utf8(bytes)
+        .map_err(|_| format!("{} is not UTF-8 text and was not attached", path.display()))?;
+    let name = path
+        .strip_prefix(working_dir)
+        .unwrap_or(&path)
+        .display()
+        .to_string();
+    Ok(Attachment {
+        path,
+        name,
+        contents,
+    })
+}
+
+// Check a new attachment against the total size of the ones already added
+pub fn check_total_size(attachments: &[Attachment], new: &Attachment) -> Result<(), String> {
+    let total: usize = attachments.iter().map(|a| a.contents.len()).sum();
+    if total + new.contents.len() > MAX_TOTAL_SIZE {
+        return Err(format!(
+            "Attachments are limited to {} KB in total",
+            MAX_TOTAL_SIZE / 1024
+        ));
+    }
+    Ok(())
+}
+
+// The message text followed by the attached files
+pub fn message_with_attachments(message: &str, attachments: &[Attachment]) -> String {
+    let mut content = message.trim_end().to_string();
+    for attachment in attachments {
+        if !content.is_empty() {
+            content.push_str("\n\n");
+        }
+        content.push_str(&attachment.to_markdown());
+    }
+    content
+}
+
+// Files under `root` whose relative path fuzzily matches `query`, best first
+pub fn fuzzy_find(root: &Path, query: &str, limit: usize) -> Vec<String> {
+    let mut scored: Vec<(i64, String)> = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    let mut walked = 0;
+    'walk: while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) {
+                    stack.push(path);
+                }
+                continue;
+            }
+            walked += 1;
+            if walked > MAX_WALKED_FILES {
+                break 'walk;
+            }
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            if let Some(score) = fuzzy_score(&relative, query) {
+                scored.push((score, relative));
+            }
+        }
+    }
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, path)| path)
+        .collect()
+}
+
+// Subsequence match of `query` in `candidate`, preferring consecutive
+// characters, matches at word starts and short paths
+fn fuzzy_score(candidate: &str, query: &str) -> Option<i64> {
+    let candidate_chars: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0i64;
+    let mut position = 0;
+    let mut previous_match: Option<usize> = None;
+    for query_char in query.to_lowercase().chars() {
+        let found = candidate_chars[position..]
+            .iter()
+            .position(|&c| c == query_char)?;
+        let index = position + found;
+        score += 1;
+        if previous_match.is_some_and(|p| p + 1 == index) {
+            score += 5;
+        }
+        if index == 0 || matches!(candidate_chars[index - 1], '/' | '_' | '-' | '.') {
+            score += 3;
+        }
+        previous_match = Some(index);
+        position = index + 1;
+    }
+    Some(score * 100 - candidate_chars.len() as i64)
+}
+
+// The `@query` being typed before `cursor` (a char index), with the char index of its `@`
+pub fn mention_at(text: &str, cursor: usize) -> Option<(usize, String)> {
+    let before: Vec<char> = text.chars().take(cursor).collect();
+    let start = before
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map_or(0, |i| i + 1);
+    if before.get(start) != Some(&'@') {
+        return None;
+    }
+    Some((start, before[start + 1..].iter().collect()))
+}
+
+// Paths mentioned as `@path` in `text` that name files under `working_dir`
+pub fn mentioned_files(text: &str, working_dir: &Path) -> Vec<PathBuf> {
+    text.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .filter(|path| !path.is_empty())
+        .map(|path| working_dir.join(path))
+        .filter(|path| path.is_file())
+        .collect()
+}
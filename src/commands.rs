@@ -0,0 +1,300 @@
+// Commands typed on the `:` command line or as `/command` in the input box.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub enum Setting {
+    Temperature(f32),
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Model(String),
+    Export { format: ExportFormat, path: PathBuf },
+    New,
+    Clear,
+    System(String),
+    Set(Setting),
+    Edit { send: bool },
+    Retry,
+    File(PathBuf),
+    Attach(Option<PathBuf>),
+    PasteImage,
+    Template(Option<String>),
+}
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "attach",
+        usage: "attach [path]",
+        description: "Attach a file to the next message, or pick one",
+    },
+    CommandInfo {
+        name: "clear",
+        usage: "clear",
+        description: "Clear the messages, keeping the system prompt and settings",
+    },
+    CommandInfo {
+        name: "edit",
+        usage: "edit [send]",
+        description: "Compose the draft in the external editor",
+    },
+    CommandInfo {
+        name: "export",
+        usage: "export md|json <path>",
+        description: "Save the conversation to a file",
+    },
+    CommandInfo {
+        name: "file",
+        usage: "file <path>",
+        description: "Add a file to the draft as a code block",
+    },
+    CommandInfo {
+        name: "model",
+        usage: "model <name>",
+        description: "Switch model",
+    },
+    CommandInfo {
+        name: "new",
+        usage: "new",
+        description: "Start a new conversation with the default settings",
+    },
+    CommandInfo {
+        name: "paste",
+        usage: "paste",
+        description: "Attach the image on the clipboard",
+    },
+    CommandInfo {
+        name: "retry",
+        usage: "retry",
+        description: "Send the last message again",
+    },
+    CommandInfo {
+        name: "set",
+        usage: "set <option>=<value>",
+        description: "Change a setting, e.g. temperature=0.2",
+    },
+    CommandInfo {
+        name: "system",
+        usage: "system [prompt]",
+        description: "Replace the system prompt, or show it",
+    },
+    CommandInfo {
+        name: "template",
+        usage: "template [name]",
+        description: "Fill in a prompt template",
+    },
+    CommandInfo {
+        name: "temp",
+        usage: "temp <0-2>",
+        description: "Set the sampling temperature",
+    },
+];
+
+const EXPORT_FORMATS: &[&str] = &["json", "md"];
+const SETTINGS: &[&str] = &["temperature="];
+
+pub fn command_info(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|info| info.name == name)
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    };
+    let usage = || {
+        command_info(name)
+            .map(|info| format!("Usage: {}", info.usage))
+            .unwrap_or_default()
+    };
+    match name {
+        "model" => {
+            if args.is_empty() {
+                return Err(usage());
+            }
+            Ok(Command::Model(args.to_string()))
+        }
+        "export" => {
+            let (format, path) = args.split_once(char::is_whitespace).ok_or_else(usage)?;
+            let format = match format {
+                "md" | "markdown" => ExportFormat::Markdown,
+                "json" => ExportFormat::Json,
+                other => return Err(format!("Unknown export format: {}", other)),
+            };
+            Ok(Command::Export {
+                format,
+                path: expand_home(path.trim()),
+            })
+        }
+        "new" => Ok(Command::New),
+        "clear" => Ok(Command::Clear),
+        "retry" => Ok(Command::Retry),
+        "paste" => Ok(Command::PasteImage),
+        "edit" => match args {
+            "" => Ok(Command::Edit { send: false }),
+            "send" => Ok(Command::Edit { send: true }),
+            _ => Err(usage()),
+        },
+        "file" => {
+            if args.is_empty() {
+                return Err(usage());
+            }
+            Ok(Command::File(expand_home(args)))
+        }
+        "attach" => Ok(Command::Attach(
+            Some(args).filter(|path| !path.is_empty()).map(expand_home),
+        )),
+        "system" => Ok(Command::System(args.to_string())),
+        "template" => Ok(Command::Template(
+            Some(args.to_string()).filter(|name| !name.is_empty()),
+        )),
+        "set" => parse_setting(args).map(Command::Set),
+        "temp" => parse_setting(&format!("temperature={}", args)).map(Command::Set),
+        "" => Err("No command given".to_string()),
+        other => Err(format!("Not a command: {}", other)),
+    }
+}
+
+fn parse_setting(args: &str) -> Result<Setting, String> {
+    let (option, value) = args
+        .split_once('=')
+        .ok_or_else(|| "Usage: set <option>=<value>".to_string())?;
+    match option.trim() {
+        "temperature" => {
+            let value: f32 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid temperature: {}", value.trim()))?;
+            if !(0.0..=2.0).contains(&value) {
+                return Err("Temperature must be between 0 and 2".to_string());
+            }
+            Ok(Setting::Temperature(value))
+        }
+        other => Err(format!("Unknown option: {}", other)),
+    }
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .map(|home| home.join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+// Full-line completions for a partially typed command line
+pub fn complete(line: &str, models: &[String]) -> Vec<String> {
+    let Some((name, arg)) = line.split_once(' ') else {
+        return COMMANDS
+            .iter()
+            .filter(|info| info.name.starts_with(line))
+            .map(|info| info.name.to_string())
+            .collect();
+    };
+    let options: Vec<String> = match name {
+        "model" => models.to_vec(),
+        "export" => EXPORT_FORMATS.iter().map(|f| f.to_string()).collect(),
+        "edit" => vec!["send".to_string()],
+        "set" => SETTINGS.iter().map(|s| s.to_string()).collect(),
+        "file" | "attach" => {
+            return complete_path(arg)
+                .into_iter()
+                .map(|p| format!("{} {}", name, p))
+                .collect();
+        }
+        _ => Vec::new(),
+    };
+    options
+        .into_iter()
+        .filter(|o| o.starts_with(arg))
+        .map(|o| format!("{} {}", name, o))
+        .collect()
+}
+
+// Entries of the directory being typed whose names start with the typed prefix.
+// Directories get a trailing slash so completion can continue into them.
+fn complete_path(partial: &str) -> Vec<String> {
+    let (dir_part, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let dir = if dir_part.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir_part)
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = Path::new(&dir).join(&name).is_dir();
+            Some(format!(
+                "{}{}{}",
+                dir_part,
+                name,
+                if is_dir { "/" } else { "" }
+            ))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+// Complete `line` as far as it is unambiguous. Returns the remaining
+// choices when there is more than one.
+pub fn complete_in_place(line: &mut String, models: &[String]) -> Vec<String> {
+    let candidates = complete(line, models);
+    match candidates.len() {
+        0 => Vec::new(),
+        1 => {
+            *line = candidates[0].clone();
+            // Completing a command name moves on to its argument
+            if !line.contains(' ') {
+                line.push(' ');
+            }
+            Vec::new()
+        }
+        _ => {
+            *line = common_prefix(&candidates);
+            candidates
+        }
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(prefix) {
+            let mut end = prefix.len() - 1;
+            while !prefix.is_char_boundary(end) {
+                end -= 1;
+            }
+            prefix = &prefix[..end];
+        }
+    }
+    prefix.to_string()
+}
@@ -0,0 +1,818 @@
+// Vim editing of the message draft while in NORMAL mode. The draft is edited
+// as a list of chars and the cursor is a char index, which is what egui's
+// TextEdit cursor uses too.
+use std::collections::HashMap;
+
+const UNNAMED_REGISTER: char = '"';
+
+// Registers shared by draft edits and message yanks in the chat view
+#[derive(Debug, Default)]
+pub struct Registers {
+    contents: HashMap<char, String>,
+}
+
+impl Registers {
+    pub fn get(&self, name: Option<char>) -> Option<&str> {
+        self.contents
+            .get(&name.unwrap_or(UNNAMED_REGISTER))
+            .map(String::as_str)
+    }
+
+    // Writing to a named register also fills the unnamed one, as in vim
+    pub fn set(&mut self, name: Option<char>, text: String) {
+        if let Some(name) = name.filter(|&n| n != UNNAMED_REGISTER) {
+            self.contents.insert(name, text.clone());
+        }
+        self.contents.insert(UNNAMED_REGISTER, text);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DraftKey {
+    Char(char),
+    Escape,
+    Redo,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DraftOutcome {
+    // Switch to INSERT mode with the TextEdit cursor at `DraftEditor::cursor`
+    Insert,
+    // A yank or delete wrote to this register
+    Yanked(Option<char>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Target {
+    Motion(Motion),
+    Object { inner: bool, kind: char },
+    Line,
+    Selection,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Parsed {
+    Incomplete,
+    Invalid,
+    Move(Motion),
+    Operate(Operator, Target),
+    SelectObject { inner: bool, kind: char },
+    DeleteChar,
+    ReplaceChar(char),
+    Paste { before: bool },
+    Undo,
+    Insert(InsertAt),
+    ToggleVisual,
+}
+
+#[derive(Debug, Default)]
+pub struct DraftEditor {
+    pub cursor: usize,
+    // Start of the visual selection, set while in visual mode
+    pub visual_anchor: Option<usize>,
+    keys: Vec<char>,
+    count: Option<usize>,
+    operator_count: Option<usize>,
+    register: Option<char>,
+    awaiting_register: bool,
+    undo_stack: Vec<(String, usize)>,
+    redo_stack: Vec<(String, usize)>,
+}
+
+impl DraftEditor {
+    pub fn feed(
+        &mut self,
+        key: DraftKey,
+        text: &mut String,
+        registers: &mut Registers,
+    ) -> Option<DraftOutcome> {
+        let ch = match key {
+            DraftKey::Escape => {
+                self.reset_pending();
+                self.visual_anchor = None;
+                return None;
+            }
+            DraftKey::Redo => {
+                self.reset_pending();
+                self.redo(text);
+                return None;
+            }
+            DraftKey::Char(ch) => ch,
+        };
+
+        if self.awaiting_register {
+            self.awaiting_register = false;
+            self.register = Some(ch);
+            return None;
+        }
+        if ch == '"' && self.keys.is_empty() && self.count.is_none() {
+            self.awaiting_register = true;
+            return None;
+        }
+        // Counts may come before the command and again after an operator (2d3w)
+        if let Some(digit) = ch.to_digit(10) {
+            let slot = if self.keys.is_empty() {
+                &mut self.count
+            } else {
+                &mut self.operator_count
+            };
+            if digit != 0 || slot.is_some() {
+                let count = slot.unwrap_or(0);
+                *slot = Some(count.saturating_mul(10).saturating_add(digit as usize));
+                return None;
+            }
+        }
+
+        self.keys.push(ch);
+        let parsed = self.parse();
+        if parsed == Parsed::Incomplete {
+            return None;
+        }
+        let count = self.count.unwrap_or(1) * self.operator_count.unwrap_or(1);
+        let register = self.register;
+        self.reset_pending();
+        let outcome = self.apply(parsed, count, register, text, registers);
+        if outcome != Some(DraftOutcome::Insert) {
+            self.clamp_cursor(text);
+        }
+        outcome
+    }
+
+    // Typed keys that haven't formed a command yet, for the mode indicator
+    pub fn pending_keys(&self) -> String {
+        let mut keys = String::new();
+        if let Some(register) = self.register {
+            keys.push('"');
+            keys.push(register);
+        } else if self.awaiting_register {
+            keys.push('"');
+        }
+        if let Some(count) = self.count {
+            keys.push_str(&count.to_string());
+        }
+        keys.extend(self.keys.iter());
+        if let Some(count) = self.operator_count {
+            keys.push_str(&count.to_string());
+        }
+        keys
+    }
+
+    pub fn is_visual(&self) -> bool {
+        self.visual_anchor.is_some()
+    }
+
+    // Selection as a half-open char range, including the char under the cursor
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.visual_anchor?;
+        Some((anchor.min(self.cursor), anchor.max(self.cursor) + 1))
+    }
+
+    // Called whenever INSERT mode starts so `u` undoes the whole insert
+    pub fn begin_insert(&mut self, text: &str) {
+        self.save_undo(text);
+    }
+
+    // Called when INSERT mode ends; vim steps the cursor back onto the last typed char
+    pub fn end_insert(&mut self, text: &str, cursor: usize) {
+        if self
+            .undo_stack
+            .last()
+            .is_some_and(|(before, _)| before == text)
+        {
+            self.undo_stack.pop();
+        }
+        let chars: Vec<char> = text.chars().collect();
+        self.cursor = cursor.min(chars.len());
+        if self.cursor > line_start(&chars, self.cursor) {
+            self.cursor -= 1;
+        }
+        self.reset_pending();
+        self.visual_anchor = None;
+    }
+
+    pub fn clamp_cursor(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        self.cursor = self.cursor.min(chars.len());
+        let start = line_start(&chars, self.cursor);
+        let end = line_end(&chars, self.cursor);
+        if self.cursor >= end && end > start {
+            self.cursor = end - 1;
+        }
+        if let Some(anchor) = self.visual_anchor {
+            self.visual_anchor = Some(anchor.min(chars.len().saturating_sub(1)));
+        }
+    }
+
+    fn reset_pending(&mut self) {
+        self.keys.clear();
+        self.count = None;
+        self.operator_count = None;
+        self.register = None;
+        self.awaiting_register = false;
+    }
+
+    fn parse(&self) -> Parsed {
+        let visual = self.is_visual();
+        match self.keys.as_slice() {
+            [] => Parsed::Incomplete,
+            [key] if parse_motion(*key).is_some() => Parsed::Move(parse_motion(*key).unwrap()),
+            ['v'] => Parsed::ToggleVisual,
+            ['u'] => Parsed::Undo,
+            ['p'] => Parsed::Paste { before: false },
+            ['P'] => Parsed::Paste { before: true },
+            ['r'] => Parsed::Incomplete,
+            ['r', ch] => Parsed::ReplaceChar(*ch),
+            ['d' | 'x'] if visual => Parsed::Operate(Operator::Delete, Target::Selection),
+            ['c' | 's'] if visual => Parsed::Operate(Operator::Change, Target::Selection),
+            ['y'] if visual => Parsed::Operate(Operator::Yank, Target::Selection),
+            ['i' | 'a'] if visual => Parsed::Incomplete,
+            [scope @ ('i' | 'a'), kind] if visual => Parsed::SelectObject {
+                inner: *scope == 'i',
+                kind: *kind,
+            },
+            ['x'] => Parsed::DeleteChar,
+            ['D'] => Parsed::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+            ['C'] => Parsed::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+            ['i'] => Parsed::Insert(InsertAt::Cursor),
+            ['a'] => Parsed::Insert(InsertAt::After),
+            ['I'] => Parsed::Insert(InsertAt::LineStart),
+            ['A'] => Parsed::Insert(InsertAt::LineEnd),
+            ['o'] => Parsed::Insert(InsertAt::LineBelow),
+            ['O'] => Parsed::Insert(InsertAt::LineAbove),
+            [op] if parse_operator(*op).is_some() => Parsed::Incomplete,
+            [op, key] if parse_operator(*op).is_some() => {
+                let operator = parse_operator(*op).unwrap();
+                if key == op {
+                    Parsed::Operate(operator, Target::Line)
+                } else if let Some(motion) = parse_motion(*key) {
+                    Parsed::Operate(operator, Target::Motion(motion))
+                } else if matches!(key, 'i' | 'a') {
+                    Parsed::Incomplete
+                } else {
+                    Parsed::Invalid
+                }
+            }
+            [op, scope @ ('i' | 'a'), kind] if parse_operator(*op).is_some() => Parsed::Operate(
+                parse_operator(*op).unwrap(),
+                Target::Object {
+                    inner: *scope == 'i',
+                    kind: *kind,
+                },
+            ),
+            _ => Parsed::Invalid,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        parsed: Parsed,
+        count: usize,
+        register: Option<char>,
+        text: &mut String,
+        registers: &mut Registers,
+    ) -> Option<DraftOutcome> {
+        let mut chars: Vec<char> = text.chars().collect();
+        self.cursor = self.cursor.min(chars.len());
+        match parsed {
+            Parsed::Incomplete | Parsed::Invalid => None,
+            Parsed::Move(motion) => {
+                for _ in 0..count {
+                    self.cursor = apply_motion(&chars, self.cursor, motion);
+                }
+                None
+            }
+            Parsed::ToggleVisual => {
+                self.visual_anchor = match self.visual_anchor {
+                    Some(_) => None,
+                    None => Some(self.cursor),
+                };
+                None
+            }
+            Parsed::SelectObject { inner, kind } => {
+                let (start, end) = text_object(&chars, self.cursor, inner, kind)?;
+                if end > start {
+                    self.visual_anchor = Some(start);
+                    self.cursor = end - 1;
+                }
+                None
+            }
+            Parsed::Undo => {
+                for _ in 0..count {
+                    self.undo(text);
+                }
+                None
+            }
+            Parsed::DeleteChar => {
+                let end = (self.cursor + count).min(line_end(&chars, self.cursor));
+                if end == self.cursor {
+                    return None;
+                }
+                self.save_undo(text);
+                let removed: String = chars.drain(self.cursor..end).collect();
+                registers.set(register, removed);
+                *text = chars.into_iter().collect();
+                Some(DraftOutcome::Yanked(register))
+            }
+            Parsed::ReplaceChar(ch) => {
+                let end = self.cursor + count;
+                if end > line_end(&chars, self.cursor) || ch == '\n' {
+                    return None;
+                }
+                self.save_undo(text);
+                for c in &mut chars[self.cursor..end] {
+                    *c = ch;
+                }
+                self.cursor = end - 1;
+                *text = chars.into_iter().collect();
+                None
+            }
+            Parsed::Paste { before } => {
+                let pasted = registers.get(register)?.repeat(count);
+                if pasted.is_empty() {
+                    return None;
+                }
+                self.save_undo(text);
+                let mut pasted: Vec<char> = pasted.chars().collect();
+                // Text yanked with whole lines is pasted on its own line
+                let at = if pasted.last() == Some(&'\n') {
+                    if before {
+                        line_start(&chars, self.cursor)
+                    } else {
+                        let end = line_end(&chars, self.cursor);
+                        if end == chars.len() {
+                            pasted.pop();
+                            pasted.insert(0, '\n');
+                            end
+                        } else {
+                            end + 1
+                        }
+                    }
+                } else if before || chars.is_empty() || chars[self.cursor..].first() == Some(&'\n')
+                {
+                    self.cursor
+                } else {
+                    self.cursor + 1
+                };
+                let len = pasted.len();
+                chars.splice(at..at, pasted);
+                self.cursor = at + len - 1;
+                *text = chars.into_iter().collect();
+                None
+            }
+            Parsed::Insert(at) => {
+                self.save_undo(text);
+                let start = line_start(&chars, self.cursor);
+                let end = line_end(&chars, self.cursor);
+                self.cursor = match at {
+                    InsertAt::Cursor => self.cursor,
+                    InsertAt::After => (self.cursor + 1).min(end),
+                    InsertAt::LineStart => first_non_blank(&chars, self.cursor),
+                    InsertAt::LineEnd => end,
+                    InsertAt::LineBelow => {
+                        chars.insert(end, '\n');
+                        end + 1
+                    }
+                    InsertAt::LineAbove => {
+                        chars.insert(start, '\n');
+                        start
+                    }
+                };
+                *text = chars.into_iter().collect();
+                self.visual_anchor = None;
+                Some(DraftOutcome::Insert)
+            }
+            Parsed::Operate(operator, target) => {
+                let (start, end, linewise) = self.target_range(&chars, target, operator, count)?;
+                self.visual_anchor = None;
+                let mut yanked: String = chars[start..end].iter().collect();
+                if linewise && !yanked.ends_with('\n') {
+                    yanked.push('\n');
+                }
+                registers.set(register, yanked);
+                match operator {
+                    Operator::Yank => {
+                        self.cursor = start;
+                        Some(DraftOutcome::Yanked(register))
+                    }
+                    Operator::Delete => {
+                        self.save_undo(text);
+                        // Deleting the last lines also removes the newline before them
+                        let start =
+                            if linewise && start > 0 && !chars[start..end].ends_with(&['\n']) {
+                                start - 1
+                            } else {
+                                start
+                            };
+                        chars.drain(start..end);
+                        self.cursor = line_start(&chars, start);
+                        *text = chars.into_iter().collect();
+                        Some(DraftOutcome::Yanked(register))
+                    }
+                    Operator::Change => {
+                        self.save_undo(text);
+                        // Changing whole lines keeps an empty line to type into
+                        let end = if linewise && chars[start..end].last() == Some(&'\n') {
+                            end - 1
+                        } else {
+                            end
+                        };
+                        chars.drain(start..end);
+                        self.cursor = start;
+                        *text = chars.into_iter().collect();
+                        Some(DraftOutcome::Insert)
+                    }
+                }
+            }
+        }
+    }
+
+    // Half-open char range an operator acts on, and whether it covers whole lines
+    fn target_range(
+        &self,
+        chars: &[char],
+        target: Target,
+        operator: Operator,
+        count: usize,
+    ) -> Option<(usize, usize, bool)> {
+        let cursor = self.cursor;
+        match target {
+            Target::Selection => {
+                let (start, end) = self.selection()?;
+                Some((start, end.min(chars.len()), false))
+            }
+            Target::Object { inner, kind } => {
+                let (start, end) = text_object(chars, cursor, inner, kind)?;
+                Some((start, end, false))
+            }
+            Target::Line => Some(line_range(chars, cursor, count)),
+            Target::Motion(Motion::Up) => {
+                let mut first = cursor;
+                for _ in 0..count {
+                    first = apply_motion(chars, first, Motion::Up);
+                }
+                let lines = count_lines(chars, first, cursor);
+                Some(line_range(chars, first, lines))
+            }
+            Target::Motion(Motion::Down) => {
+                let mut last = cursor;
+                for _ in 0..count {
+                    last = apply_motion(chars, last, Motion::Down);
+                }
+                Some(line_range(chars, cursor, count_lines(chars, cursor, last)))
+            }
+            Target::Motion(Motion::LineEnd) => Some((cursor, line_end(chars, cursor), false)),
+            Target::Motion(Motion::Right) => {
+                let end = (cursor + count).min(line_end(chars, cursor));
+                (end > cursor).then_some((cursor, end, false))
+            }
+            Target::Motion(motion) => {
+                let on_word = chars.get(cursor).is_some_and(|c| !c.is_whitespace());
+                let (target, inclusive) = match motion {
+                    // cw acts like ce and stays on the current word, as in vim
+                    Motion::WordForward if operator == Operator::Change && on_word => {
+                        let mut target = word_end_from(chars, cursor);
+                        for _ in 1..count {
+                            target = apply_motion(chars, target, Motion::WordEnd);
+                        }
+                        (target, true)
+                    }
+                    _ => {
+                        let mut target = cursor;
+                        for _ in 0..count {
+                            target = apply_motion(chars, target, motion);
+                        }
+                        // dw on the last word of a line stops at the line end
+                        if motion == Motion::WordForward {
+                            target = target.min(line_end(chars, cursor).max(cursor + 1));
+                        }
+                        (target, motion == Motion::WordEnd)
+                    }
+                };
+                let (start, end) = if target < cursor {
+                    (target, cursor)
+                } else if inclusive {
+                    (cursor, (target + 1).min(chars.len()))
+                } else {
+                    (cursor, target.min(chars.len()))
+                };
+                (end > start).then_some((start, end, false))
+            }
+        }
+    }
+
+    // Remember `text` so `u` can go back to it, also used when the draft is
+    // replaced from outside the editor
+    pub fn save_undo(&mut self, text: &str) {
+        self.undo_stack.push((text.to_string(), self.cursor));
+        self.redo_stack.clear();
+    }
+
+    fn undo(&mut self, text: &mut String) {
+        if let Some((before, cursor)) = self.undo_stack.pop() {
+            let current = std::mem::replace(text, before);
+            self.redo_stack.push((current, self.cursor));
+            self.cursor = cursor;
+        }
+    }
+
+    fn redo(&mut self, text: &mut String) {
+        if let Some((after, cursor)) = self.redo_stack.pop() {
+            let current = std::mem::replace(text, after);
+            self.undo_stack.push((current, self.cursor));
+            self.cursor = cursor;
+            self.clamp_cursor(text);
+        }
+    }
+}
+
+fn parse_motion(key: char) -> Option<Motion> {
+    Some(match key {
+        'h' => Motion::Left,
+        'l' => Motion::Right,
+        'k' => Motion::Up,
+        'j' => Motion::Down,
+        'w' => Motion::WordForward,
+        'b' => Motion::WordBackward,
+        'e' => Motion::WordEnd,
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        _ => return None,
+    })
+}
+
+fn parse_operator(key: char) -> Option<Operator> {
+    Some(match key {
+        'd' => Operator::Delete,
+        'c' => Operator::Change,
+        'y' => Operator::Yank,
+        _ => return None,
+    })
+}
+
+fn line_start(chars: &[char], pos: usize) -> usize {
+    chars[..pos.min(chars.len())]
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |i| i + 1)
+}
+
+// Index of the newline ending the line, or the buffer length on the last line
+fn line_end(chars: &[char], pos: usize) -> usize {
+    let pos = pos.min(chars.len());
+    chars[pos..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map_or(chars.len(), |i| pos + i)
+}
+
+fn first_non_blank(chars: &[char], pos: usize) -> usize {
+    let start = line_start(chars, pos);
+    let end = line_end(chars, pos);
+    (start..end)
+        .find(|&i| !chars[i].is_whitespace())
+        .unwrap_or(end)
+}
+
+fn count_lines(chars: &[char], from: usize, to: usize) -> usize {
+    chars[from.min(to)..from.max(to)]
+        .iter()
+        .filter(|&&c| c == '\n')
+        .count()
+        + 1
+}
+
+// `count` whole lines starting at the line containing `pos`, with their newline
+fn line_range(chars: &[char], pos: usize, count: usize) -> (usize, usize, bool) {
+    let start = line_start(chars, pos);
+    let mut end = line_end(chars, pos);
+    for _ in 1..count {
+        if end < chars.len() {
+            end = line_end(chars, end + 1);
+        }
+    }
+    (start, (end + 1).min(chars.len()), true)
+}
+
+// 0 = whitespace, 1 = word characters, 2 = punctuation
+fn char_class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn word_end_from(chars: &[char], pos: usize) -> usize {
+    let class = char_class(chars[pos]);
+    let mut i = pos;
+    while i + 1 < chars.len() && char_class(chars[i + 1]) == class {
+        i += 1;
+    }
+    i
+}
+
+fn apply_motion(chars: &[char], pos: usize, motion: Motion) -> usize {
+    let len = chars.len();
+    match motion {
+        Motion::Left => {
+            if pos > line_start(chars, pos) {
+                pos - 1
+            } else {
+                pos
+            }
+        }
+        Motion::Right => {
+            if pos + 1 < line_end(chars, pos) {
+                pos + 1
+            } else {
+                pos
+            }
+        }
+        Motion::Up | Motion::Down => {
+            let start = line_start(chars, pos);
+            let column = pos - start;
+            let target_start = if motion == Motion::Up {
+                if start == 0 {
+                    return pos;
+                }
+                line_start(chars, start - 1)
+            } else {
+                let end = line_end(chars, pos);
+                if end == len {
+                    return pos;
+                }
+                end + 1
+            };
+            let target_end = line_end(chars, target_start);
+            (target_start + column).min(target_end.saturating_sub(1).max(target_start))
+        }
+        Motion::LineStart => line_start(chars, pos),
+        Motion::FirstNonBlank => first_non_blank(chars, pos),
+        Motion::LineEnd => {
+            let start = line_start(chars, pos);
+            line_end(chars, pos).saturating_sub(1).max(start)
+        }
+        Motion::WordForward => {
+            if pos >= len {
+                return len;
+            }
+            let mut i = pos;
+            let class = char_class(chars[i]);
+            if class != 0 {
+                while i < len && char_class(chars[i]) == class {
+                    i += 1;
+                }
+            }
+            while i < len && char_class(chars[i]) == 0 {
+                i += 1;
+            }
+            i
+        }
+        Motion::WordBackward => {
+            if pos == 0 {
+                return 0;
+            }
+            let mut i = pos.min(len) - 1;
+            while i > 0 && char_class(chars[i]) == 0 {
+                i -= 1;
+            }
+            let class = char_class(chars[i]);
+            while i > 0 && char_class(chars[i - 1]) == class {
+                i -= 1;
+            }
+            i
+        }
+        Motion::WordEnd => {
+            let mut i = pos + 1;
+            while i < len && char_class(chars[i]) == 0 {
+                i += 1;
+            }
+            if i >= len {
+                return len.saturating_sub(1).max(pos.min(len));
+            }
+            word_end_from(chars, i)
+        }
+    }
+}
+
+// Half-open range of the text object `kind` around `pos`: words, quotes and brackets
+fn text_object(chars: &[char], pos: usize, inner: bool, kind: char) -> Option<(usize, usize)> {
+    match kind {
+        'w' => {
+            let pos = pos.min(chars.len().checked_sub(1)?);
+            let class = char_class(chars[pos]);
+            let mut start = pos;
+            while start > 0 && char_class(chars[start - 1]) == class {
+                start -= 1;
+            }
+            let mut end = word_end_from(chars, pos) + 1;
+            if !inner {
+                let word_end = end;
+                while end < chars.len() && chars[end] != '\n' && char_class(chars[end]) == 0 {
+                    end += 1;
+                }
+                // Without trailing whitespace, `aw` takes the leading whitespace
+                if end == word_end {
+                    while start > 0 && chars[start - 1] != '\n' && char_class(chars[start - 1]) == 0
+                    {
+                        start -= 1;
+                    }
+                }
+            }
+            Some((start, end))
+        }
+        '"' | '\'' | '`' => {
+            let start = line_start(chars, pos);
+            let end = line_end(chars, pos);
+            let quotes: Vec<usize> = (start..end).filter(|&i| chars[i] == kind).collect();
+            let (open, close) = quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(_, close)| pos <= close)?;
+            Some(if inner {
+                (open + 1, close)
+            } else {
+                (open, close + 1)
+            })
+        }
+        _ => {
+            let (open_char, close_char) = match kind {
+                '(' | ')' | 'b' => ('(', ')'),
+                '[' | ']' => ('[', ']'),
+                '{' | '}' | 'B' => ('{', '}'),
+                '<' | '>' => ('<', '>'),
+                _ => return None,
+            };
+            let pos = pos.min(chars.len().checked_sub(1)?);
+            let open = if chars[pos] == open_char {
+                pos
+            } else {
+                let mut depth = 0;
+                let mut found = None;
+                for i in (0..pos).rev() {
+                    if chars[i] == close_char {
+                        depth += 1;
+                    } else if chars[i] == open_char {
+                        if depth == 0 {
+                            found = Some(i);
+                            break;
+                        }
+                        depth -= 1;
+                    }
+                }
+                found?
+            };
+            let mut depth = 0;
+            let close = (open + 1..chars.len()).find(|&i| {
+                if chars[i] == open_char {
+                    depth += 1;
+                } else if chars[i] == close_char {
+                    if depth == 0 {
+                        return true;
+                    }
+                    depth -= 1;
+                }
+                false
+            })?;
+            Some(if inner {
+                (open + 1, close)
+            } else {
+                (open, close + 1)
+            })
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Composing the draft in an external editor ($VISUAL / $EDITOR).
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct EditorConfig {
+    // Editor command, overrides $VISUAL and $EDITOR. GUI editors need their
+    // wait flag, e.g. "code --wait" or "subl -w".
+    pub command: Option<String>,
+    // Terminal to run terminal editors in, e.g. "alacritty -e" or "kitty".
+    // It has to stay open until the editor exits.
+    pub terminal: Option<String>,
+    // Send the draft as soon as the editor exits
+    #[serde(default)]
+    pub send_on_exit: bool,
+}
+
+fn editor_command(config: &EditorConfig) -> String {
+    config
+        .command
+        .clone()
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+fn draft_path() -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!("md-chat-draft-{}-{}.md", std::process::id(), nanos))
+}
+
+// Write `text` to a temporary markdown file, open it in the editor and
+// return the edited text once the editor exits. Blocks until then.
+pub fn edit_in_editor(text: &str, config: &EditorConfig) -> Result<String, String> {
+    let path = draft_path();
+    fs::write(&path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+
+    let editor = editor_command(config);
+    let mut words: Vec<&str> = config
+        .terminal
+        .as_deref()
+        .map(|terminal| terminal.split_whitespace().collect())
+        .unwrap_or_default();
+    words.extend(editor.split_whitespace());
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| "No editor configured".to_string())?;
+
+    let status = Command::new(program).args(args).arg(&path).status();
+    let result = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e)),
+        Ok(status) => Err(format!("{} exited with {}", editor, status)),
+        Err(e) => Err(format!("Could not run {}: {}", program, e)),
+    };
+    let _ = fs::remove_file(&path);
+    result
+}
@@ -0,0 +1,403 @@
+// Key chords for INSERT mode and the NORMAL mode chat view, configurable
+// through the `[keybindings]` section of config.toml. The vim grammar of the
+// draft editor (motions, operators) is fixed.
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    // INSERT mode
+    Send,
+    NormalMode,
+    // NORMAL mode
+    InsertMode,
+    ScrollDown,
+    ScrollUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    NextMessage,
+    PrevMessage,
+    Refresh,
+    SetMark,
+    JumpMark,
+    Register,
+    YankMessage,
+    CommandLine,
+    SwitchContext,
+    Compose,
+    Templates,
+    PasteImage,
+}
+
+const INSERT_ACTIONS: &[(&str, Action)] =
+    &[("send", Action::Send), ("normal_mode", Action::NormalMode)];
+
+const NORMAL_ACTIONS: &[(&str, Action)] = &[
+    ("insert_mode", Action::InsertMode),
+    ("scroll_down", Action::ScrollDown),
+    ("scroll_up", Action::ScrollUp),
+    ("page_down", Action::PageDown),
+    ("page_up", Action::PageUp),
+    ("top", Action::Top),
+    ("bottom", Action::Bottom),
+    ("next_message", Action::NextMessage),
+    ("prev_message", Action::PrevMessage),
+    ("refresh", Action::Refresh),
+    ("set_mark", Action::SetMark),
+    ("jump_mark", Action::JumpMark),
+    ("register", Action::Register),
+    ("yank_message", Action::YankMessage),
+    ("command_line", Action::CommandLine),
+    ("switch_context", Action::SwitchContext),
+    ("compose", Action::Compose),
+    ("templates", Action::Templates),
+    ("paste_image", Action::PasteImage),
+];
+
+fn default_insert_bindings(preset: &str) -> Vec<(&'static str, Vec<&'static str>)> {
+    let send = match preset {
+        "ctrl_enter" => "Ctrl+Enter",
+        _ => "Enter",
+    };
+    vec![("send", vec![send]), ("normal_mode", vec!["Escape"])]
+}
+
+fn default_normal_bindings() -> Vec<(&'static str, Vec<&'static str>)> {
+    vec![
+        ("insert_mode", vec!["i"]),
+        ("scroll_down", vec!["j"]),
+        ("scroll_up", vec!["k"]),
+        ("page_down", vec!["J"]),
+        ("page_up", vec!["K"]),
+        ("top", vec!["g"]),
+        ("bottom", vec!["G"]),
+        ("next_message", vec!["]"]),
+        ("prev_message", vec!["["]),
+        ("refresh", vec!["r"]),
+        ("set_mark", vec!["m"]),
+        ("jump_mark", vec!["'", "`"]),
+        ("register", vec!["\""]),
+        ("yank_message", vec!["y"]),
+        ("command_line", vec![":"]),
+        ("switch_context", vec!["Tab"]),
+        ("compose", vec!["E"]),
+        ("templates", vec!["T"]),
+        ("paste_image", vec!["P"]),
+    ]
+}
+
+pub const PRESETS: &[&str] = &["default", "ctrl_enter"];
+
+// Typed characters match text input, so `G` or `:` work on any layout.
+// Named keys and chords with Ctrl/Alt/Cmd match key presses.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyChord {
+    Char(char),
+    Key {
+        modifiers: egui::Modifiers,
+        key: egui::Key,
+    },
+}
+
+impl KeyChord {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let mut chars = text.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            return Ok(KeyChord::Char(ch));
+        }
+        // The key name is last so "Ctrl++" binds the plus key
+        let (modifier_names, key_name) = match text.strip_suffix("++") {
+            Some(rest) => (rest, "+"),
+            None => text.rsplit_once('+').unwrap_or(("", text)),
+        };
+        let mut modifiers = egui::Modifiers::NONE;
+        for name in modifier_names.split('+').filter(|n| !n.is_empty()) {
+            modifiers |= match name.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => egui::Modifiers::CTRL,
+                "shift" => egui::Modifiers::SHIFT,
+                "alt" | "option" => egui::Modifiers::ALT,
+                "cmd" | "command" => egui::Modifiers::COMMAND,
+                _ => return Err(format!("Unknown modifier `{}` in `{}`", name, text)),
+            };
+        }
+        let key = egui::Key::from_name(key_name)
+            .ok_or_else(|| format!("Unknown key `{}` in `{}`", key_name, text))?;
+        // Shift+letter is the same as typing the capital letter
+        if modifiers == egui::Modifiers::SHIFT
+            && key_name.len() == 1
+            && let Some(ch) = key_name.chars().next().filter(char::is_ascii_alphabetic)
+        {
+            return Ok(KeyChord::Char(ch.to_ascii_uppercase()));
+        }
+        if modifiers.is_none() && key_name.chars().count() == 1 {
+            return Ok(KeyChord::Char(key_name.chars().next().unwrap()));
+        }
+        Ok(KeyChord::Key { modifiers, key })
+    }
+
+    fn matches_key(&self, pressed: egui::Key, pressed_modifiers: egui::Modifiers) -> bool {
+        match *self {
+            KeyChord::Key { modifiers, key } => {
+                key == pressed && pressed_modifiers.matches_exact(modifiers)
+            }
+            KeyChord::Char(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyChord::Char(ch) => write!(f, "{}", ch),
+            KeyChord::Key { modifiers, key } => {
+                let names = egui::ModifierNames::NAMES;
+                let shortcut = egui::KeyboardShortcut::new(*modifiers, *key);
+                write!(f, "{}", shortcut.format(&names, false))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ChordList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl ChordList {
+    fn chords(&self) -> Vec<&str> {
+        match self {
+            ChordList::One(chord) => vec![chord.as_str()],
+            ChordList::Many(chords) => chords.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct KeybindingsConfig {
+    pub preset: Option<String>,
+    #[serde(default)]
+    pub insert: BTreeMap<String, ChordList>,
+    #[serde(default)]
+    pub normal: BTreeMap<String, ChordList>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keybindings {
+    insert: Vec<(KeyChord, Action)>,
+    normal: Vec<(KeyChord, Action)>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self::from_config(None).0
+    }
+}
+
+impl Keybindings {
+    // Build the bindings from config, returning warnings for anything that was ignored
+    pub fn from_config(config: Option<&KeybindingsConfig>) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+        let preset = config
+            .and_then(|c| c.preset.as_deref())
+            .unwrap_or("default");
+        if !PRESETS.contains(&preset) {
+            warnings.push(format!(
+                "Unknown keybinding preset `{}`, expected one of: {}",
+                preset,
+                PRESETS.join(", ")
+            ));
+        }
+        let empty = BTreeMap::new();
+        let insert = resolve_mode(
+            "INSERT",
+            INSERT_ACTIONS,
+            default_insert_bindings(preset),
+            config.map_or(&empty, |c| &c.insert),
+            &mut warnings,
+        );
+        let normal = resolve_mode(
+            "NORMAL",
+            NORMAL_ACTIONS,
+            default_normal_bindings(),
+            config.map_or(&empty, |c| &c.normal),
+            &mut warnings,
+        );
+        for (chord, _) in &insert {
+            if let KeyChord::Char(ch) = chord {
+                warnings.push(format!(
+                    "`{}` is bound in INSERT mode but typing it would trigger the binding; use a chord like Ctrl+{}",
+                    ch, ch
+                ));
+            }
+        }
+        for (chord, action) in &normal {
+            if let KeyChord::Char(ch @ '1'..='9') = chord {
+                warnings.push(format!(
+                    "`{}` is bound to `{}` in NORMAL mode but digits are count prefixes",
+                    ch,
+                    action_name(*action)
+                ));
+            }
+        }
+        let insert = insert
+            .into_iter()
+            .filter(|(chord, _)| matches!(chord, KeyChord::Key { .. }))
+            .collect();
+        (Self { insert, normal }, warnings)
+    }
+
+    // Remove the key press for an INSERT mode action so the TextEdit doesn't see it
+    pub fn consume_insert(&self, input: &mut egui::InputState, action: Action) -> bool {
+        let chords: Vec<&KeyChord> = self.chords(&self.insert, action).collect();
+        let mut found = false;
+        input.events.retain(|event| {
+            let is_match = !found
+                && matches!(event, egui::Event::Key { key, pressed: true, modifiers, .. }
+                    if chords.iter().any(|chord| chord.matches_key(*key, *modifiers)));
+            found |= is_match;
+            !is_match
+        });
+        found
+    }
+
+    pub fn insert_pressed(&self, input: &egui::InputState, action: Action) -> bool {
+        input.events.iter().any(|event| {
+            matches!(event, egui::Event::Key { key, pressed: true, modifiers, .. }
+                if self.chords(&self.insert, action).any(|chord| chord.matches_key(*key, *modifiers)))
+        })
+    }
+
+    pub fn normal_char(&self, ch: char) -> Option<Action> {
+        self.normal
+            .iter()
+            .find(|(chord, _)| *chord == KeyChord::Char(ch))
+            .map(|(_, action)| *action)
+    }
+
+    pub fn normal_key(&self, key: egui::Key, modifiers: egui::Modifiers) -> Option<Action> {
+        self.normal
+            .iter()
+            .find(|(chord, _)| chord.matches_key(key, modifiers))
+            .map(|(_, action)| *action)
+    }
+
+    // The NORMAL mode action for a single event, if it is bound
+    pub fn normal_action(&self, event: &egui::Event) -> Option<Action> {
+        match event {
+            egui::Event::Text(text) => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => self.normal_char(ch),
+                    _ => None,
+                }
+            }
+            egui::Event::Key {
+                key,
+                pressed: true,
+                modifiers,
+                ..
+            } => self.normal_key(*key, *modifiers),
+            _ => None,
+        }
+    }
+
+    // The first chord of an action, for the pending keys display
+    pub fn hint(&self, action: Action) -> String {
+        self.chords(self.bindings_for(action), action)
+            .next()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+
+    // Human readable chords for an action, for hover texts
+    pub fn describe(&self, action: Action) -> String {
+        self.chords(self.bindings_for(action), action)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+
+    fn bindings_for(&self, action: Action) -> &[(KeyChord, Action)] {
+        if INSERT_ACTIONS.iter().any(|(_, a)| *a == action) {
+            &self.insert
+        } else {
+            &self.normal
+        }
+    }
+
+    fn chords<'a>(
+        &self,
+        bindings: &'a [(KeyChord, Action)],
+        action: Action,
+    ) -> impl Iterator<Item = &'a KeyChord> {
+        bindings
+            .iter()
+            .filter(move |(_, a)| *a == action)
+            .map(|(chord, _)| chord)
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    INSERT_ACTIONS
+        .iter()
+        .chain(NORMAL_ACTIONS)
+        .find(|(_, a)| *a == action)
+        .map_or("?", |(name, _)| name)
+}
+
+// Defaults overridden per action by the user's table, with conflicts reported
+fn resolve_mode(
+    mode: &str,
+    actions: &[(&str, Action)],
+    defaults: Vec<(&'static str, Vec<&'static str>)>,
+    overrides: &BTreeMap<String, ChordList>,
+    warnings: &mut Vec<String>,
+) -> Vec<(KeyChord, Action)> {
+    for name in overrides.keys() {
+        if !actions.iter().any(|(n, _)| n == name) {
+            warnings.push(format!("Unknown {} mode action `{}`", mode, name));
+        }
+    }
+    let mut bindings: Vec<(KeyChord, Action)> = Vec::new();
+    for &(name, action) in actions {
+        let chords = match overrides.get(name) {
+            Some(list) => list.chords(),
+            None => defaults
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, chords)| chords.clone())
+                .unwrap_or_default(),
+        };
+        for text in chords {
+            let chord = match KeyChord::parse(text) {
+                Ok(chord) => chord,
+                Err(error) => {
+                    warnings.push(format!("{} mode `{}`: {}", mode, name, error));
+                    continue;
+                }
+            };
+            if let Some((_, existing)) = bindings.iter().find(|(c, _)| *c == chord) {
+                if *existing != action {
+                    warnings.push(format!(
+                        "Keybinding conflict in {} mode: `{}` is bound to both `{}` and `{}`, keeping `{}`",
+                        mode,
+                        chord,
+                        action_name(*existing),
+                        name,
+                        action_name(*existing)
+                    ));
+                }
+                continue;
+            }
+            bindings.push((chord, action));
+        }
+    }
+    bindings
+}
@@ -1,23 +1,40 @@
 use app::{ChatMessage, MyApp};
-use dirs;
+use editor::EditorConfig;
 use eframe::egui::{IconData, ViewportBuilder};
-use image;
+use keybindings::KeybindingsConfig;
+use mcp::McpServerConfig;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use shell::ShellConfig;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
+use tools::ToolConfig;
 
 const APP_NAME: &str = "MD-Chat";
 
 mod app;
+mod attachments;
+mod commands;
+mod draft;
+mod editor;
+mod keybindings;
+mod mcp;
 mod openai;
+mod shell;
+mod templates;
+mod tools;
+mod vim;
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct AppConfig {
     openai_api_key: Option<String>,
     api_url: String,
     models: Option<Vec<String>>,
+    keybindings: Option<KeybindingsConfig>,
+    editor: Option<EditorConfig>,
+    tools: Option<Vec<ToolConfig>>,
+    mcp_servers: Option<Vec<McpServerConfig>>,
+    shell: Option<ShellConfig>,
 }
 
 fn get_config_path() -> Option<PathBuf> {
@@ -33,6 +50,11 @@ fn load_or_create_config() -> AppConfig {
         openai_api_key: None,
         api_url: "https://api.openai.com".to_string(),
         models: None,
+        keybindings: None,
+        editor: None,
+        tools: None,
+        mcp_servers: None,
+        shell: None,
     };
     if let Some(path) = get_config_path() {
         if !path.exists() {
@@ -63,7 +85,7 @@ fn default_models() -> Vec<String> {
 }
 
 async fn fetch_history() -> Result<Vec<ChatMessage>, String> {
-    let url = format!("http://localhost:3017/partition/default/instance/default/command/view/15",);
+    let url = "http://localhost:3017/partition/default/instance/default/command/view/15";
     let client = reqwest::Client::new();
     let response = client.get(url).send().await.map_err(|e| e.to_string())?;
     let text = response.text().await.map_err(|e| e.to_string())?;
@@ -0,0 +1,255 @@
+// Model Context Protocol servers declared as `[[mcp_servers]]` in config.toml.
+// Each server is a child process speaking newline-delimited JSON-RPC on
+// stdio; its tools are offered to the model next to the local ones.
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct McpServerConfig {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerStatus {
+    Starting,
+    Ready,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+struct Connection {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Connection {
+    fn send(&mut self, message: &Value) -> Result<(), String> {
+        let line = format!("{}\n", message);
+        self.stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("Could not write to the server: {}", e))
+    }
+
+    fn notify(&mut self, method: &str) -> Result<(), String> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method }))
+    }
+
+    // Send a request and wait for its response, answering anything the server asks meanwhile
+    fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stdout
+                .read_line(&mut line)
+                .map_err(|e| format!("Could not read from the server: {}", e))?;
+            if read == 0 {
+                return Err("The server exited".to_string());
+            }
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if let Some(server_method) = message.get("method").and_then(Value::as_str) {
+                if let Some(request_id) = message.get("id") {
+                    let reply = match server_method {
+                        "ping" => json!({ "jsonrpc": "2.0", "id": request_id, "result": {} }),
+                        _ => json!({
+                            "jsonrpc": "2.0",
+                            "id": request_id,
+                            "error": { "code": -32601, "message": "Method not found" }
+                        }),
+                    };
+                    self.send(&reply)?;
+                }
+                continue;
+            }
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                let text = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("Unknown error");
+                return Err(text.to_string());
+            }
+            return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+}
+
+pub struct McpServer {
+    pub name: String,
+    status: Mutex<ServerStatus>,
+    tools: Mutex<Vec<McpTool>>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl McpServer {
+    // Launch the server and list its tools in the background
+    pub fn start(config: McpServerConfig) -> Arc<Self> {
+        let server = Arc::new(Self {
+            name: config.name.clone(),
+            status: Mutex::new(ServerStatus::Starting),
+            tools: Mutex::new(Vec::new()),
+            connection: Mutex::new(None),
+        });
+        let starting = server.clone();
+        std::thread::spawn(move || {
+            let status = match connect(&config) {
+                Ok((connection, tools)) => {
+                    *starting.tools.lock().unwrap() = tools;
+                    *starting.connection.lock().unwrap() = Some(connection);
+                    ServerStatus::Ready
+                }
+                Err(error) => ServerStatus::Failed(error),
+            };
+            *starting.status.lock().unwrap() = status;
+        });
+        server
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn tools(&self) -> Vec<McpTool> {
+        self.tools.lock().unwrap().clone()
+    }
+
+    // Call a tool and return its text content. Blocks until the server answers.
+    pub fn call_tool(&self, tool: &str, arguments: &str) -> String {
+        let arguments: Value = match serde_json::from_str(arguments) {
+            Ok(arguments) => arguments,
+            Err(e) => return format!("Invalid arguments: {}", e),
+        };
+        let mut connection = self.connection.lock().unwrap();
+        let Some(connection) = connection.as_mut() else {
+            return format!("{} is not running", self.name);
+        };
+        let params = json!({ "name": tool, "arguments": arguments });
+        match connection.request("tools/call", params) {
+            Ok(result) => tool_result_text(&result),
+            Err(error) => {
+                if matches!(connection.child.try_wait(), Ok(Some(_))) {
+                    *self.status.lock().unwrap() = ServerStatus::Failed(error.clone());
+                }
+                format!("Error: {}", error)
+            }
+        }
+    }
+}
+
+impl Drop for McpServer {
+    fn drop(&mut self) {
+        if let Ok(mut connection) = self.connection.lock()
+            && let Some(connection) = connection.as_mut()
+        {
+            let _ = connection.child.kill();
+        }
+    }
+}
+
+fn connect(config: &McpServerConfig) -> Result<(Connection, Vec<McpTool>), String> {
+    let mut child = Command::new(&config.command)
+        .args(&config.args)
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run {}: {}", config.command, e))?;
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err("Could not connect to the server's stdio".to_string());
+    };
+    let mut connection = Connection {
+        child,
+        stdin,
+        stdout: BufReader::new(stdout),
+        next_id: 0,
+    };
+    let handshake = handshake(&mut connection);
+    if handshake.is_err() {
+        let _ = connection.child.kill();
+    }
+    handshake.map(|tools| (connection, tools))
+}
+
+fn handshake(connection: &mut Connection) -> Result<Vec<McpTool>, String> {
+    connection.request(
+        "initialize",
+        json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "md-chat", "version": env!("CARGO_PKG_VERSION") }
+        }),
+    )?;
+    connection.notify("notifications/initialized")?;
+    let mut tools = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let params = match &cursor {
+            Some(cursor) => json!({ "cursor": cursor }),
+            None => json!({}),
+        };
+        let result = connection.request("tools/list", params)?;
+        for tool in result["tools"].as_array().into_iter().flatten() {
+            let Some(name) = tool["name"].as_str() else {
+                continue;
+            };
+            tools.push(McpTool {
+                name: name.to_string(),
+                description: tool["description"].as_str().unwrap_or("").to_string(),
+                input_schema: tool
+                    .get("inputSchema")
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+            });
+        }
+        cursor = result["nextCursor"].as_str().map(str::to_string);
+        if cursor.is_none() {
+            return Ok(tools);
+        }
+    }
+}
+
+// The text parts of a tools/call result
+fn tool_result_text(result: &Value) -> String {
+    let mut text = String::new();
+    for part in result["content"].as_array().into_iter().flatten() {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        match part["type"].as_str() {
+            Some("text") => text.push_str(part["text"].as_str().unwrap_or("")),
+            Some(other) => text.push_str(&format!("[{} content]", other)),
+            None => {}
+        }
+    }
+    if result["isError"].as_bool() == Some(true) {
+        format!("Error: {}", text)
+    } else {
+        text
+    }
+}
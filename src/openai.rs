@@ -1,17 +1,47 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
     System,
+    #[default]
     User,
     Assistant,
+    Tool,
 }
 
-impl Default for Role {
-    fn default() -> Self {
-        Role::User
-    }
+// A message queued for the request worker along with the settings it is sent with
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub content: String,
+    // Images sent with the message as `data:` URIs
+    pub images: Vec<String>,
+    pub model: String,
+    pub system_prompt: String,
+    pub temperature: f32,
+    pub tools: Vec<ToolDefinition>,
+    // Tool calls made while answering this message, sent back with their results
+    pub tool_rounds: Vec<ToolRound>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolRound {
+    pub content: String,
+    pub calls: Vec<ToolCall>,
+    pub results: Vec<ToolResult>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolResult {
+    pub call_id: String,
+    pub content: String,
+}
+
+// The assistant's answer, or the tools it wants to call first
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
 }
 
 #[derive(Debug, Serialize)]
@@ -19,12 +49,100 @@ pub struct ChatCompletionRequest {
     pub model: String,
     pub messages: Vec<ChatCompletionMessage>,
     pub temperature: f32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ChatCompletionMessage {
     pub role: Role,
-    pub content: String,
+    pub content: MessageContent,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatCompletionMessage {
+    fn new(role: Role, content: MessageContent) -> Self {
+        Self {
+            role,
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    // JSON object, as a string
+    pub arguments: String,
+}
+
+// Plain text, or OpenAI-style content parts for messages with images
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl MessageContent {
+    fn with_images(text: &str, images: &[String]) -> Self {
+        if images.is_empty() {
+            return MessageContent::Text(text.to_string());
+        }
+        let mut parts = Vec::new();
+        if !text.is_empty() {
+            parts.push(ContentPart::Text {
+                text: text.to_string(),
+            });
+        }
+        parts.extend(images.iter().map(|url| ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.clone() },
+        }));
+        MessageContent::Parts(parts)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,29 +157,50 @@ pub struct ChatCompletionChoice {
 
 #[derive(Debug, Deserialize)]
 pub struct ChatCompletionMessageResponse {
-    pub content: String,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
 }
 
 pub async fn send_openai_request(
-    message: &str,
-    model: &str,
+    chat: &ChatRequest,
     api_key: &str,
     api_url: &str,
-) -> Result<String, String> {
+) -> Result<ChatReply, String> {
     let client = reqwest::Client::new();
+    let mut messages = vec![
+        ChatCompletionMessage::new(
+            Role::System,
+            MessageContent::Text(chat.system_prompt.clone()),
+        ),
+        ChatCompletionMessage::new(
+            Role::User,
+            MessageContent::with_images(&chat.content, &chat.images),
+        ),
+    ];
+    for round in &chat.tool_rounds {
+        messages.push(ChatCompletionMessage {
+            tool_calls: round.calls.clone(),
+            ..ChatCompletionMessage::new(
+                Role::Assistant,
+                MessageContent::Text(round.content.clone()),
+            )
+        });
+        for result in &round.results {
+            messages.push(ChatCompletionMessage {
+                tool_call_id: Some(result.call_id.clone()),
+                ..ChatCompletionMessage::new(
+                    Role::Tool,
+                    MessageContent::Text(result.content.clone()),
+                )
+            });
+        }
+    }
     let request = ChatCompletionRequest {
-        model: model.to_string(),
-        messages: vec![
-            ChatCompletionMessage {
-                role: Role::System,
-                content: "You are a helpful assistant. You can use markdown formatting in your responses.".to_string(),
-            },
-            ChatCompletionMessage {
-                role: Role::User,
-                content: message.to_string(),
-            },
-        ],
-        temperature: 0.7,
+        model: chat.model.clone(),
+        messages,
+        temperature: chat.temperature,
+        tools: chat.tools.clone(),
     };
 
     let response = client
@@ -72,14 +211,14 @@ pub async fn send_openai_request(
         .await
         .map_err(|e| e.to_string())?;
 
-    let completion: ChatCompletionResponse = response
-        .json()
-        .await
-        .map_err(|e| e.to_string())?;
+    let completion: ChatCompletionResponse = response.json().await.map_err(|e| e.to_string())?;
 
     completion
         .choices
         .first()
-        .map(|choice| choice.message.content.clone())
+        .map(|choice| ChatReply {
+            content: choice.message.content.clone().unwrap_or_default(),
+            tool_calls: choice.message.tool_calls.clone(),
+        })
         .ok_or_else(|| "No response from OpenAI".to_string())
-} 
\ No newline at end of file
+}
@@ -0,0 +1,203 @@
+// The built-in `run_shell_command` tool, enabled with a `[shell]` section in
+// config.toml. Every command is approved in the UI before it runs, runs with
+// a timeout in the configured directory, and is appended to an audit log.
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::commands::expand_home;
+use crate::templates::utc_date;
+
+pub const SHELL_TOOL: &str = "run_shell_command";
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ShellConfig {
+    // The tool is only offered to the model when enabled
+    #[serde(default)]
+    pub enabled: bool,
+    // Directory commands run in, the current directory by default
+    pub working_dir: Option<String>,
+    // Seconds before a command is killed
+    pub timeout: Option<u64>,
+    // Command the shell is wrapped in, e.g. "firejail --quiet --net=none"
+    // or "bwrap --ro-bind / / --dev /dev --unshare-net"
+    pub sandbox: Option<String>,
+    // Start commands with an empty environment (PATH and HOME are kept)
+    #[serde(default)]
+    pub clear_env: bool,
+    // Where executed commands are logged, `shell.log` next to config.toml by default
+    pub audit_log: Option<String>,
+}
+
+impl ShellConfig {
+    pub fn working_dir(&self) -> PathBuf {
+        self.working_dir
+            .as_deref()
+            .map(expand_home)
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS))
+    }
+
+    fn audit_log(&self) -> Option<PathBuf> {
+        match &self.audit_log {
+            Some(path) => Some(expand_home(path)),
+            None => dirs::config_dir().map(|dir| dir.join("MD-Chat").join("shell.log")),
+        }
+    }
+
+    pub fn parameters() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "The command line to run with sh -c"
+                }
+            },
+            "required": ["command"]
+        })
+    }
+
+    pub fn description(&self) -> String {
+        format!(
+            "Run a shell command in {} after the user approves it. Returns the exit code, stdout and stderr.",
+            self.working_dir().display()
+        )
+    }
+}
+
+// The command line in a call's arguments
+pub fn command_argument(arguments: &str) -> Option<String> {
+    let arguments: serde_json::Value = serde_json::from_str(arguments).ok()?;
+    arguments["command"].as_str().map(str::to_string)
+}
+
+// Run `command` with `sh -c`, killing it after the timeout. Blocks until it is done.
+pub fn run_command(config: &ShellConfig, arguments: &str) -> String {
+    let Some(command) = command_argument(arguments) else {
+        return "Invalid arguments: expected {\"command\": \"...\"}".to_string();
+    };
+    let working_dir = config.working_dir();
+    let mut words: Vec<&str> = config
+        .sandbox
+        .as_deref()
+        .map(|sandbox| sandbox.split_whitespace().collect())
+        .unwrap_or_default();
+    words.extend(["sh", "-c", command.as_str()]);
+    let mut process = Command::new(words[0]);
+    process
+        .args(&words[1..])
+        .current_dir(&working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so a timeout also kills what it started
+        .process_group(0);
+    if config.clear_env {
+        process.env_clear();
+        for name in ["PATH", "HOME"] {
+            if let Ok(value) = std::env::var(name) {
+                process.env(name, value);
+            }
+        }
+    }
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => return format!("Could not run {}: {}", words[0], e),
+    };
+    // Read both pipes while waiting so a chatty command can't block on a full pipe
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if started.elapsed() < config.timeout() => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            _ => {
+                let _ = Command::new("kill")
+                    .args(["-KILL", &format!("-{}", child.id())])
+                    .status();
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+        }
+    };
+    let collect = |reader: Option<thread::JoinHandle<String>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    let (stdout, stderr) = (collect(stdout), collect(stderr));
+    let exit = match status {
+        Some(status) => match status.code() {
+            Some(code) => format!("exit code: {}", code),
+            None => format!("exit: {}", status),
+        },
+        None => format!("timed out after {}s", config.timeout().as_secs()),
+    };
+    audit(config, &working_dir, &exit, &command);
+    format!("{}\nstdout:\n{}\nstderr:\n{}", exit, stdout, stderr)
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        String::from_utf8_lossy(&bytes).to_string()
+    })
+}
+
+fn audit(config: &ShellConfig, working_dir: &std::path::Path, exit: &str, command: &str) {
+    let Some(path) = config.audit_log() else {
+        return;
+    };
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let line = format!(
+        "{} {:02}:{:02}:{:02} UTC\t{}\t{}\t{}\n",
+        utc_date(secs),
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        working_dir.display(),
+        exit,
+        command.replace('\n', "\\n")
+    );
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = result {
+        eprintln!("Could not write {}: {}", path.display(), e);
+    }
+}
+#[cfg(test)]
+mod scratch {
+    #[test]
+    fn t() {
+        let c = super::ShellConfig { enabled: true, timeout: Some(1), audit_log: Some("/tmp/audit.log".into()), ..Default::default() };
+        let start = std::time::Instant::now();
+        println!("{}", super::run_command(&c, r#"{"command":"echo hi; echo err >&2; sleep 100 & sleep 50"}"#));
+        println!("{:?}", start.elapsed());
+        println!("{}", super::run_command(&c, r#"{"command":"exit 3"}"#));
+    }
+}
@@ -0,0 +1,172 @@
+// Prompt templates with `{{variable}}` placeholders, loaded from the
+// `templates` directory next to config.toml. A template is either a
+// markdown file (the file name is the template name) or a TOML file:
+//
+//     name = "Translate"
+//     description = "Translate text"
+//     template = "Translate into {{language}}:\n\n{{clipboard}}"
+//     [defaults]
+//     language = "French"
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::expand_home;
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub name: String,
+    pub description: String,
+    pub body: String,
+    pub defaults: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct TemplateFile {
+    name: Option<String>,
+    #[serde(default)]
+    description: String,
+    template: String,
+    #[serde(default)]
+    defaults: BTreeMap<String, String>,
+}
+
+pub fn templates_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("MD-Chat").join("templates"))
+}
+
+// All templates sorted by name, and a message for each file that couldn't be read
+pub fn load_templates() -> (Vec<Template>, Vec<String>) {
+    let mut templates = Vec::new();
+    let mut errors = Vec::new();
+    let Some(dir) = templates_dir() else {
+        return (templates, errors);
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return (templates, errors);
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        match load_template(&path) {
+            Ok(Some(template)) => templates.push(template),
+            Ok(None) => {}
+            Err(error) => errors.push(format!("{}: {}", path.display(), error)),
+        }
+    }
+    templates.sort_by_key(|t| t.name.to_lowercase());
+    (templates, errors)
+}
+
+fn load_template(path: &Path) -> Result<Option<Template>, String> {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension {
+        "md" | "markdown" | "txt" => {
+            let body = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            Ok(Some(Template {
+                name: stem,
+                description: String::new(),
+                body,
+                defaults: BTreeMap::new(),
+            }))
+        }
+        "toml" => {
+            let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let file: TemplateFile = toml::from_str(&contents).map_err(|e| e.to_string())?;
+            Ok(Some(Template {
+                name: file.name.unwrap_or(stem),
+                description: file.description,
+                body: file.template,
+                defaults: file.defaults,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    matches!(name, "clipboard" | "date") || name.starts_with("file:")
+}
+
+// Placeholders in order of appearance as (start, end, name), where
+// start..end covers the braces
+fn placeholders(body: &str) -> Vec<(usize, usize, &str)> {
+    let mut found = Vec::new();
+    let mut rest = 0;
+    while let Some(open) = body[rest..].find("{{") {
+        let start = rest + open;
+        let Some(close) = body[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + close + 2;
+        found.push((start, end, body[start + 2..end - 2].trim()));
+        rest = end;
+    }
+    found
+}
+
+// Variables the user has to fill in, without the built-in ones
+pub fn variables(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (_, _, name) in placeholders(body) {
+        if !name.is_empty() && !is_builtin(name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+pub fn render(body: &str, values: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut last = 0;
+    for (start, end, name) in placeholders(body) {
+        rendered.push_str(&body[last..start]);
+        let value = match name {
+            "clipboard" => arboard::Clipboard::new()
+                .and_then(|mut clipboard| clipboard.get_text())
+                .map_err(|e| format!("Could not read the clipboard: {}", e))?,
+            "date" => today(),
+            _ => match name.strip_prefix("file:") {
+                Some(path) => {
+                    let path = expand_home(path.trim());
+                    fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?
+                }
+                None => values.get(name).cloned().unwrap_or_default(),
+            },
+        };
+        rendered.push_str(&value);
+        last = end;
+    }
+    rendered.push_str(&body[last..]);
+    Ok(rendered)
+}
+
+// Today's date (UTC) as YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    utc_date(secs)
+}
+
+// The UTC date of a Unix timestamp as YYYY-MM-DD
+pub fn utc_date(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
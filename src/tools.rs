@@ -0,0 +1,257 @@
+// Local tools the model can call, declared as `[[tools]]` in config.toml.
+// A tool is a shell command: `{{name}}` placeholders are replaced with the
+// (shell-quoted) arguments and the full arguments object is written to its
+// stdin as JSON. Whatever it prints is sent back as the tool result.
+// Tools of MCP servers are offered as `<server>__<tool>`, and the built-in
+// shell tool as `run_shell_command`.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use crate::mcp::{McpServer, McpServerConfig, ServerStatus};
+use crate::openai::{FunctionDefinition, ToolDefinition};
+use crate::shell::{self, SHELL_TOOL, ShellConfig};
+
+const MCP_SEPARATOR: &str = "__";
+
+// Longer output is cut off before it is sent back to the model
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolConfig {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub command: String,
+    // JSON schema of the arguments object
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<ToolConfig>,
+    servers: Vec<Arc<McpServer>>,
+    shell: Option<ShellConfig>,
+}
+
+// What runs a call: a local command or a tool of an MCP server
+#[derive(Clone)]
+pub enum ToolRunner {
+    Local(ToolConfig),
+    Mcp(Arc<McpServer>, String),
+    Shell(ShellConfig),
+}
+
+impl ToolRunner {
+    // Blocks until the tool is done
+    pub fn run(&self, arguments: &str) -> String {
+        match self {
+            ToolRunner::Local(tool) => run_tool(tool, arguments),
+            ToolRunner::Mcp(server, tool) => truncate(server.call_tool(tool, arguments)),
+            ToolRunner::Shell(config) => truncate(shell::run_command(config, arguments)),
+        }
+    }
+
+    // What will run, for calls where the raw arguments don't show it plainly
+    pub fn preview(&self, arguments: &str) -> Option<String> {
+        let ToolRunner::Shell(config) = self else {
+            return None;
+        };
+        let command = shell::command_argument(arguments)?;
+        let mut preview = format!(
+            "In {} (timeout {}s)",
+            config.working_dir().display(),
+            config.timeout().as_secs()
+        );
+        if let Some(sandbox) = &config.sandbox {
+            preview.push_str(&format!("\nSandbox: {}", sandbox));
+        }
+        preview.push_str(&format!("\n\n$ {}", command));
+        Some(preview)
+    }
+}
+
+impl ToolRegistry {
+    // Tools with a duplicate or empty name are skipped and reported.
+    // MCP servers are started in the background.
+    pub fn from_config(
+        config: Option<&Vec<ToolConfig>>,
+        mcp_servers: Option<&Vec<McpServerConfig>>,
+        shell: Option<&ShellConfig>,
+    ) -> (Self, Vec<String>) {
+        let shell = shell.filter(|shell| shell.enabled).cloned();
+        let mut tools: Vec<ToolConfig> = Vec::new();
+        let mut warnings = Vec::new();
+        for tool in config.into_iter().flatten() {
+            if tool.name.trim().is_empty() {
+                warnings.push("tools: a tool has no name".to_string());
+            } else if tool.name == SHELL_TOOL {
+                warnings.push(format!("tools: {} is the name of the shell tool", SHELL_TOOL));
+            } else if tools.iter().any(|t| t.name == tool.name) {
+                warnings.push(format!("tools: {} is declared twice", tool.name));
+            } else {
+                tools.push(tool.clone());
+            }
+        }
+        let mut servers: Vec<Arc<McpServer>> = Vec::new();
+        for server in mcp_servers.into_iter().flatten() {
+            if server.name.trim().is_empty() || server.name.contains(MCP_SEPARATOR) {
+                warnings.push(format!(
+                    "mcp_servers: {:?} is not a valid server name",
+                    server.name
+                ));
+            } else if servers.iter().any(|s| s.name == server.name) {
+                warnings.push(format!("mcp_servers: {} is declared twice", server.name));
+            } else {
+                servers.push(McpServer::start(server.clone()));
+            }
+        }
+        (
+            Self {
+                tools,
+                servers,
+                shell,
+            },
+            warnings,
+        )
+    }
+
+    pub fn servers(&self) -> &[Arc<McpServer>] {
+        &self.servers
+    }
+
+    // Local tools and the tools of the servers that are running
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        let local = self.tools.iter().map(|tool| {
+            definition(
+                tool.name.clone(),
+                tool.description.clone(),
+                tool.parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            )
+        });
+        let remote = self
+            .servers
+            .iter()
+            .filter(|server| matches!(server.status(), ServerStatus::Ready))
+            .flat_map(|server| {
+                server.tools().into_iter().map(|tool| {
+                    definition(
+                        format!("{}{}{}", server.name, MCP_SEPARATOR, tool.name),
+                        tool.description,
+                        tool.input_schema,
+                    )
+                })
+            });
+        let shell = self.shell.iter().map(|shell| {
+            definition(
+                SHELL_TOOL.to_string(),
+                shell.description(),
+                ShellConfig::parameters(),
+            )
+        });
+        local.chain(remote).chain(shell).collect()
+    }
+
+    pub fn runner(&self, name: &str) -> Option<ToolRunner> {
+        if name == SHELL_TOOL {
+            return self.shell.clone().map(ToolRunner::Shell);
+        }
+        if let Some(tool) = self.tools.iter().find(|tool| tool.name == name) {
+            return Some(ToolRunner::Local(tool.clone()));
+        }
+        let (server_name, tool) = name.split_once(MCP_SEPARATOR)?;
+        let server = self.servers.iter().find(|s| s.name == server_name)?;
+        server
+            .tools()
+            .iter()
+            .any(|t| t.name == tool)
+            .then(|| ToolRunner::Mcp(server.clone(), tool.to_string()))
+    }
+}
+
+fn definition(name: String, description: String, parameters: serde_json::Value) -> ToolDefinition {
+    ToolDefinition {
+        kind: "function".to_string(),
+        function: FunctionDefinition {
+            name,
+            description,
+            parameters,
+        },
+    }
+}
+
+// Run a tool with the JSON arguments the model gave. Blocks until the command exits.
+fn run_tool(tool: &ToolConfig, arguments: &str) -> String {
+    let args: BTreeMap<String, serde_json::Value> = match serde_json::from_str(arguments) {
+        Ok(args) => args,
+        Err(e) => return format!("Invalid arguments: {}", e),
+    };
+    let command = fill_placeholders(&tool.command, &args);
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return format!("Could not run {}: {}", tool.name, e),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands that don't read their input close it early, which is fine
+        let _ = stdin.write_all(arguments.as_bytes());
+    }
+    let output = match child.wait_with_output() {
+        Ok(output) => output,
+        Err(e) => return format!("Could not run {}: {}", tool.name, e),
+    };
+    let mut result = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        result.push_str(&format!("\nstderr:\n{}", stderr));
+    }
+    if !output.status.success() {
+        result.push_str(&format!("\n{} exited with {}", tool.name, output.status));
+    }
+    truncate(result)
+}
+
+fn fill_placeholders(command: &str, args: &BTreeMap<String, serde_json::Value>) -> String {
+    let mut filled = command.to_string();
+    for (name, value) in args {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        filled = filled.replace(&format!("{{{{{}}}}}", name), &shell_quote(&value));
+    }
+    // Optional arguments the model left out are empty
+    while let Some(start) = filled.find("{{") {
+        let Some(len) = filled[start..].find("}}") else {
+            break;
+        };
+        filled.replace_range(start..start + len + 2, "''");
+    }
+    filled
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT_LEN {
+        let mut end = MAX_OUTPUT_LEN;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
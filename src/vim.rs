@@ -0,0 +1,145 @@
+// NORMAL mode key sequences in the chat view: count prefixes, marks,
+// registers and the `:` trigger. Which keys start a command comes from the
+// keybindings; counts and the names of marks and registers are typed.
+use eframe::egui;
+
+use crate::keybindings::{Action, Keybindings};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NormalCommand {
+    Insert,
+    ScrollDown,
+    ScrollUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    NextMessage,
+    PrevMessage,
+    Refresh,
+    SetMark(char),
+    JumpMark(char),
+    YankMessage(Option<char>),
+    CommandLine,
+    SwitchContext,
+    Compose,
+    Templates,
+    PasteImage,
+}
+
+#[derive(Debug, Default)]
+pub struct NormalState {
+    count: Option<usize>,
+    // Action waiting for the name of a mark or register
+    pending: Option<Action>,
+    register: Option<char>,
+}
+
+impl NormalState {
+    // Feed one typed character. Returns the command once a full sequence
+    // has been typed, together with the count prefix if one was given.
+    pub fn feed_char(
+        &mut self,
+        ch: char,
+        bindings: &Keybindings,
+    ) -> Option<(Option<usize>, NormalCommand)> {
+        if let Some(action) = self.pending.take() {
+            let count = self.count.take();
+            return match action {
+                Action::SetMark if ch.is_ascii_alphabetic() => {
+                    Some((count, NormalCommand::SetMark(ch)))
+                }
+                Action::JumpMark if ch.is_ascii_alphabetic() => {
+                    Some((count, NormalCommand::JumpMark(ch)))
+                }
+                Action::Register => {
+                    self.count = count;
+                    self.register = Some(ch);
+                    None
+                }
+                _ => None,
+            };
+        }
+
+        // A leading 0 is not a count, matching vim
+        if let Some(digit) = ch.to_digit(10)
+            && (digit != 0 || self.count.is_some())
+        {
+            let count = self.count.unwrap_or(0);
+            self.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+            return None;
+        }
+
+        match bindings.normal_char(ch) {
+            Some(action) => self.feed_action(action),
+            None => {
+                self.reset();
+                None
+            }
+        }
+    }
+
+    // Feed a key press. Only chords like Tab or Ctrl+D are matched here,
+    // plain characters arrive through `feed_char`.
+    pub fn feed_key(
+        &mut self,
+        key: egui::Key,
+        modifiers: egui::Modifiers,
+        bindings: &Keybindings,
+    ) -> Option<(Option<usize>, NormalCommand)> {
+        if self.pending.is_some() {
+            return None;
+        }
+        self.feed_action(bindings.normal_key(key, modifiers)?)
+    }
+
+    fn feed_action(&mut self, action: Action) -> Option<(Option<usize>, NormalCommand)> {
+        let command = match action {
+            Action::InsertMode => NormalCommand::Insert,
+            Action::ScrollDown => NormalCommand::ScrollDown,
+            Action::ScrollUp => NormalCommand::ScrollUp,
+            Action::PageDown => NormalCommand::PageDown,
+            Action::PageUp => NormalCommand::PageUp,
+            Action::Top => NormalCommand::Top,
+            Action::Bottom => NormalCommand::Bottom,
+            Action::NextMessage => NormalCommand::NextMessage,
+            Action::PrevMessage => NormalCommand::PrevMessage,
+            Action::Refresh => NormalCommand::Refresh,
+            Action::CommandLine => NormalCommand::CommandLine,
+            Action::SwitchContext => NormalCommand::SwitchContext,
+            Action::Compose => NormalCommand::Compose,
+            Action::Templates => NormalCommand::Templates,
+            Action::PasteImage => NormalCommand::PasteImage,
+            Action::YankMessage => NormalCommand::YankMessage(self.register.take()),
+            Action::SetMark | Action::JumpMark | Action::Register => {
+                self.pending = Some(action);
+                return None;
+            }
+            Action::Send | Action::NormalMode => return None,
+        };
+        self.register = None;
+        Some((self.count.take(), command))
+    }
+
+    pub fn reset(&mut self) {
+        self.count = None;
+        self.pending = None;
+        self.register = None;
+    }
+
+    // Partially typed sequence, shown next to the mode indicator like vim's showcmd
+    pub fn pending_keys(&self, bindings: &Keybindings) -> String {
+        let mut keys = String::new();
+        if let Some(register) = self.register {
+            keys.push_str(&bindings.hint(Action::Register));
+            keys.push(register);
+        }
+        if let Some(count) = self.count {
+            keys.push_str(&count.to_string());
+        }
+        if let Some(action) = self.pending {
+            keys.push_str(&bindings.hint(action));
+        }
+        keys
+    }
+}